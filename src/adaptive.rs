@@ -0,0 +1,150 @@
+//! The --adaptive-samples refinement, which renders the grid once, then
+//! spends extra samples on the coordinates whose orbits pass through the
+//! most detailed parts of that first pass.
+//!
+//! Detail is measured in image space, as the gradient of the first pass, but
+//! samples are drawn in coordinate space, and an orbit crosses many pixels.
+//! Each coordinate is weighted by the largest gradient any point of its
+//! orbit lands on, which favours orbits that reach the detail at all, even
+//! if they mostly fall elsewhere. As with --seed-from, the extra samples
+//! aren't reweighted, so detailed areas end up brighter than a uniform
+//! render would make them.
+
+use std::sync::atomic::Ordering;
+
+use image::{LumaA, Pixel};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+
+use crate::{
+    autofocus::gradient, cell_random, iterate_coordinate, progress::ProgressFile, to_image_coord,
+    Args, Canvas, Image, INTERRUPTED,
+};
+
+/// The largest gradient under any point of the orbit from `coord`, or 0 if
+/// it isn't drawn.
+fn peak_gradient(coord: (f64, f64), gradient: &[f64], args: &Args) -> f64 {
+    let orbit = match iterate_coordinate(coord, args) {
+        Some(orbit) => orbit,
+        None => return 0.0,
+    };
+
+    let size = args.size as i32;
+    orbit
+        .points
+        .iter()
+        .map(|&z| to_image_coord(z, args))
+        .filter(|&(x, y)| (0..size).contains(&x) && (0..size).contains(&y))
+        .map(|(x, y)| gradient[(y * size + x) as usize])
+        .fold(0.0, f64::max)
+}
+
+pub fn render(
+    coords: &[(f64, f64)],
+    samples: usize,
+    args: &Args,
+    bar: &ProgressBar,
+    progress_file: Option<&ProgressFile>,
+) -> Canvas {
+    let pass_args = Args {
+        adaptive_samples: None,
+        ..args.clone()
+    };
+    let canvas = crate::render(coords, &pass_args, bar, progress_file);
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return canvas;
+    }
+
+    let mut image = Image::from_pixel(args.size, args.size, LumaA([0, u16::MAX]));
+    image
+        .pixels_mut()
+        .zip(canvas.image().pixels())
+        .for_each(|(o, i)| o.blend(i));
+    let gradient = gradient(&image);
+
+    let weights: Vec<_> = coords
+        .par_iter()
+        .map(|&coord| peak_gradient(coord, &gradient, args))
+        .collect();
+    let weighted = weights.iter().filter(|&&w| w > 0.0).count();
+    let mut total = 0.0;
+    let totals: Vec<_> = weights
+        .into_iter()
+        .map(|w| {
+            total += w;
+            total
+        })
+        .collect();
+    if total == 0.0 {
+        eprintln!("Warning: the first pass has no detail to refine, skipping --adaptive-samples");
+        return canvas;
+    }
+
+    let (delta_re, delta_im) = args.grid_spacing();
+    // Each extra sample picks a coordinate in proportion to its weight, then
+    // moves to a random point in the cell after it so repeats don't retrace
+    // the same orbit.
+    let extra: Vec<_> = (0..samples)
+        .into_par_iter()
+        .map(|i| {
+            let target = cell_random(args.seed, i, 1, 0) * total;
+            let n = totals
+                .partition_point(|&t| t <= target)
+                .min(totals.len() - 1);
+            let (x, y) = coords[n];
+            (
+                x + cell_random(args.seed, i, 1, 1) * delta_re,
+                y + cell_random(args.seed, i, 1, 2) * delta_im,
+            )
+        })
+        .collect();
+
+    bar.inc_length(extra.len().div_ceil(args.work_chunk_len()) as u64);
+    let refined = crate::render(&extra, &pass_args, bar, progress_file);
+    eprintln!(
+        "Refined with {} samples over {} of {} coordinates",
+        samples,
+        weighted,
+        coords.len()
+    );
+
+    canvas.merge(&refined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sample_grid, tests::args};
+
+    const SMALL: &[&str] = &[
+        "-s", "64", "-z", "16", "-d", "0.05", "-l", "50", "-m", "Escaped",
+    ];
+
+    #[test]
+    fn weights_by_the_orbit_peak() {
+        let args = args(SMALL);
+        let mut gradient = vec![0.0; 64 * 64];
+        // The orbit from 0.3 starts at 0.3 and then escapes to the right.
+        let (x, y) = to_image_coord(num::complex::Complex64::new(0.3, 0.0), &args);
+        gradient[(y * 64 + x) as usize] = 5.0;
+        assert_eq!(peak_gradient((0.3, 0.0), &gradient, &args), 5.0);
+        // Trapped orbits aren't drawn in escaped mode.
+        assert_eq!(peak_gradient((0.0, 0.0), &gradient, &args), 0.0);
+    }
+
+    #[test]
+    fn adds_to_the_first_pass() {
+        let args = args(SMALL);
+        let grid = sample_grid(&args);
+        let bar = ProgressBar::hidden();
+        let first = crate::render(&grid, &args, &bar, None).density;
+        let refined = render(&grid, 500, &args, &bar, None).density;
+
+        assert!(first
+            .pixels()
+            .zip(refined.pixels())
+            .all(|(a, b)| a[0] <= b[0]));
+        let total = |d: &crate::Density| d.pixels().map(|p| p[0] as u64).sum::<u64>();
+        assert!(total(&refined) > total(&first));
+    }
+}
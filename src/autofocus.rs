@@ -0,0 +1,160 @@
+//! Picks a centre for the render from a coarse preview, by looking for the
+//! region where the orbit density changes the most.
+
+use image::{LumaA, Pixel};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+
+use crate::{can_reject_bulbs, in_main_bulbs, render, sample_grid, to_complex_coord, Args, Image};
+
+/// Side length of the preview, in pixels.
+const PREVIEW_SIZE: u32 = 256;
+/// How much coarser than the full render the preview samples the grid.
+const DELTA_SCALE: f64 = 4.0;
+/// The preview is split into this many windows per side when scoring.
+const WINDOWS: u32 = 8;
+
+/// Sobel gradient magnitude of the image's luma, zero at the border.
+pub fn gradient(image: &Image) -> Vec<f64> {
+    let (w, h) = image.dimensions();
+    let luma = |x: u32, y: u32| image.get_pixel(x, y).0[0] as f64;
+
+    let mut out = vec![0.0; (w * h) as usize];
+    for y in 1..h.saturating_sub(1) {
+        for x in 1..w.saturating_sub(1) {
+            let gx = luma(x + 1, y - 1) + 2.0 * luma(x + 1, y) + luma(x + 1, y + 1)
+                - luma(x - 1, y - 1)
+                - 2.0 * luma(x - 1, y)
+                - luma(x - 1, y + 1);
+            let gy = luma(x - 1, y + 1) + 2.0 * luma(x, y + 1) + luma(x + 1, y + 1)
+                - luma(x - 1, y - 1)
+                - 2.0 * luma(x, y - 1)
+                - luma(x + 1, y - 1);
+            out[(y * w + x) as usize] = gx.hypot(gy);
+        }
+    }
+
+    out
+}
+
+/// Finds the pixel at the centre of the square window with the largest
+/// total gradient.
+fn busiest_window(gradient: &[f64], size: u32) -> (u32, u32) {
+    let window = (size / WINDOWS).max(1);
+    let stride = size as usize + 1;
+
+    // Summed area table, with a zero row and column in front.
+    let mut sums = vec![0.0; stride * stride];
+    for y in 0..size as usize {
+        for x in 0..size as usize {
+            sums[(y + 1) * stride + x + 1] = gradient[y * size as usize + x]
+                + sums[y * stride + x + 1]
+                + sums[(y + 1) * stride + x]
+                - sums[y * stride + x];
+        }
+    }
+
+    let total = |x: usize, y: usize| {
+        let w = window as usize;
+        sums[(y + w) * stride + x + w] - sums[y * stride + x + w] - sums[(y + w) * stride + x]
+            + sums[y * stride + x]
+    };
+
+    let mut best = (0, 0);
+    let mut best_total = f64::NEG_INFINITY;
+    for y in 0..=(size - window) as usize {
+        for x in 0..=(size - window) as usize {
+            let t = total(x, y);
+            if t > best_total {
+                best_total = t;
+                best = (x as u32, y as u32);
+            }
+        }
+    }
+
+    (best.0 + window / 2, best.1 + window / 2)
+}
+
+/// Renders a coarse preview of `args`, then moves its offsets so the busiest
+/// area of the preview is in the centre.
+pub fn centre(args: &mut Args) {
+    let mut preview = args.clone();
+    preview.size = PREVIEW_SIZE.min(args.size);
+    preview.zoom = args.zoom * preview.size as f64 / args.size as f64;
+    preview.delta = args.delta * DELTA_SCALE;
+    preview.delta_re = args.delta_re.map(|d| d * DELTA_SCALE);
+    preview.delta_im = args.delta_im.map(|d| d * DELTA_SCALE);
+    preview.rgb_phase = false;
+    preview.trajectory_direction = false;
+    preview.trap_attractor_cluster = false;
+    preview.depth_colours = None;
+    preview.target_quality = None;
+    preview.adaptive_samples = None;
+
+    let mut coords = sample_grid(&preview);
+    if can_reject_bulbs(&preview) {
+        coords = coords
+            .into_par_iter()
+            .filter(|&coord| !in_main_bulbs(coord))
+            .collect();
+    }
+
+    let canvas = render(&coords, &preview, &ProgressBar::hidden(), None).image();
+    let mut image = Image::from_pixel(preview.size, preview.size, LumaA([0, u16::MAX]));
+    image
+        .pixels_mut()
+        .zip(canvas.pixels())
+        .for_each(|(o, i)| o.blend(i));
+
+    let (x, y) = busiest_window(&gradient(&image), preview.size);
+    let centre = match to_complex_coord(x, y, &preview) {
+        Some(centre) => centre,
+        None => {
+            eprintln!("Warning: autofocus found nothing inside the projection, keeping the centre");
+            return;
+        }
+    };
+    args.re_off = -centre.re;
+    args.im_off = -centre.im;
+
+    // The offsets now hold the result, so a recorded render reproduces this
+    // framing rather than searching again from it.
+    args.autofocus = false;
+
+    eprintln!(
+        "Autofocus centre: {} {:+}i (-r={} -i={})",
+        centre.re, centre.im, args.re_off, args.im_off
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gradient_follows_edges() {
+        // Dark on the left, bright from x = 4.
+        let image = Image::from_fn(8, 6, |x, _| LumaA([if x < 4 { 0 } else { 1000 }, u16::MAX]));
+        let gradient = gradient(&image);
+        for y in 0..6 {
+            for x in 0..8 {
+                let g = gradient[(y * 8 + x) as usize];
+                let on_edge = (x == 3 || x == 4) && y > 0 && y < 5;
+                assert_eq!(g, if on_edge { 4000.0 } else { 0.0 }, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_the_busiest_window() {
+        let size = 64;
+        let mut gradient = vec![1.0; (size * size) as usize];
+        // A busy patch filling the 8 pixel window at (40, 16).
+        for y in 16..24 {
+            for x in 40..48 {
+                gradient[y * size as usize + x] = 10.0;
+            }
+        }
+        assert_eq!(busiest_window(&gradient, size), (44, 20));
+    }
+}
@@ -0,0 +1,108 @@
+//! Sampling points for --boundary-seed, drawn from the cells of a coarse
+//! grid that straddle the edge of the set instead of from the whole grid.
+//!
+//! The coarse grid covers `[-bounds, bounds)` with --boundary-resolution
+//! cells per side, and each cell corner is tested with the escape time
+//! test, to the iteration limit. A cell whose corners disagree has the
+//! boundary running through it, or at least some point close to it. Points
+//! are spread evenly over those cells, at a uniformly random position
+//! within each. Finer resolutions hug the boundary more closely, but miss
+//! filaments narrower than a cell either way, and take longer to classify.
+
+use num::complex::Complex64;
+use rayon::prelude::*;
+
+use crate::{cell_random, escapes, warp_coordinate, Args};
+
+/// The corners of the coarse grid, in row order.
+fn corner_escapes(args: &Args, resolution: usize, cell: f64) -> Vec<bool> {
+    let side = resolution + 1;
+    (0..side * side)
+        .into_par_iter()
+        .map(|n| {
+            let corner = (
+                -args.bounds + (n % side) as f64 * cell,
+                -args.bounds + (n / side) as f64 * cell,
+            );
+            let (x, y) = warp_coordinate(corner, args);
+            escapes(Complex64::new(x, y), args)
+        })
+        .collect()
+}
+
+/// Draws `count` points from the boundary cells, or returns `None` if no
+/// cell has the boundary in it.
+pub fn sample(count: usize, args: &Args) -> Option<Vec<(f64, f64)>> {
+    let resolution = args.boundary_resolution as usize;
+    let cell = 2.0 * args.bounds / resolution as f64;
+    let escapes = corner_escapes(args, resolution, cell);
+
+    let side = resolution + 1;
+    let cells: Vec<_> = (0..resolution * resolution)
+        .filter(|&n| {
+            let (x, y) = (n % resolution, n / resolution);
+            let corner = |dx: usize, dy: usize| escapes[(y + dy) * side + x + dx];
+            let first = corner(0, 0);
+            first != corner(1, 0) || first != corner(0, 1) || first != corner(1, 1)
+        })
+        .collect();
+    if cells.is_empty() {
+        return None;
+    }
+    eprintln!(
+        "Boundary seeding found {} of {} cells on the boundary",
+        cells.len(),
+        resolution * resolution
+    );
+
+    let points = (0..count)
+        .map(|i| {
+            let n = cells[i % cells.len()];
+            let (x, y) = (
+                (n % resolution) as f64 + cell_random(args.seed, i, 2, 0),
+                (n / resolution) as f64 + cell_random(args.seed, i, 2, 1),
+            );
+            (-args.bounds + x * cell, -args.bounds + y * cell)
+        })
+        .collect();
+    Some(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::args;
+
+    #[test]
+    fn samples_cells_on_the_boundary() {
+        let args = args(&["-l", "50", "--boundary-resolution", "32"]);
+        let cell = 2.0 * args.bounds / 32.0;
+        let points = sample(1000, &args).unwrap();
+        assert_eq!(points.len(), 1000);
+
+        let escape = |re: f64, im: f64| escapes(Complex64::new(re, im), &args);
+        for &(re, im) in &points {
+            let x = ((re + args.bounds) / cell).floor() * cell - args.bounds;
+            let y = ((im + args.bounds) / cell).floor() * cell - args.bounds;
+            let corners = [
+                escape(x, y),
+                escape(x + cell, y),
+                escape(x, y + cell),
+                escape(x + cell, y + cell),
+            ];
+            assert!(
+                corners.contains(&true) && corners.contains(&false),
+                "{} {:+}i",
+                re,
+                im
+            );
+        }
+    }
+
+    #[test]
+    fn finds_nothing_without_a_boundary() {
+        // The whole square lies inside the main cardioid.
+        let args = args(&["-b", "0.2", "--boundary-resolution", "8"]);
+        assert_eq!(sample(10, &args), None);
+    }
+}
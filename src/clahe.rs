@@ -0,0 +1,127 @@
+//! Contrast limited adaptive histogram equalisation of the luma canvas.
+//!
+//! The image is split into square tiles, each of which gets its own clipped
+//! histogram equalisation curve. Pixels are then mapped by bilinearly
+//! interpolating between the curves of the four nearest tile centres, which
+//! hides the tile boundaries.
+
+use rayon::prelude::*;
+
+use crate::Image;
+
+const BINS: usize = 4096;
+const BIN_SHIFT: u32 = 16 - 12;
+
+/// How far above the mean bin count a histogram bin may reach before it's
+/// clipped and the excess redistributed.
+const CLIP_FACTOR: f64 = 4.0;
+
+fn tile_curve(image: &Image, x0: u32, y0: u32, tile: u32) -> Vec<u16> {
+    let x1 = (x0 + tile).min(image.width());
+    let y1 = (y0 + tile).min(image.height());
+
+    let mut hist = vec![0_u64; BINS];
+    for y in y0..y1 {
+        for x in x0..x1 {
+            hist[(image.get_pixel(x, y)[0] >> BIN_SHIFT) as usize] += 1;
+        }
+    }
+
+    let count = ((x1 - x0) * (y1 - y0)) as u64;
+    let limit = ((CLIP_FACTOR * count as f64 / BINS as f64) as u64).max(1);
+
+    let mut excess = 0;
+    for h in &mut hist {
+        if *h > limit {
+            excess += *h - limit;
+            *h = limit;
+        }
+    }
+
+    let share = excess / BINS as u64;
+    let mut remainder = excess % BINS as u64;
+    let mut total = 0;
+    hist.iter()
+        .map(|&h| {
+            total += h + share;
+            if remainder > 0 {
+                total += 1;
+                remainder -= 1;
+            }
+
+            (total * u16::MAX as u64 / count) as u16
+        })
+        .collect()
+}
+
+/// Finds the two tiles either side of `pos` along one axis, and how far
+/// between their centres `pos` lies.
+fn neighbours(pos: u32, tile: u32, tiles: usize) -> (usize, usize, f32) {
+    let f = (pos as f32 + 0.5) / tile as f32 - 0.5;
+    let lower = (f.floor().max(0.0) as usize).min(tiles - 1);
+    let upper = (lower + 1).min(tiles - 1);
+
+    (lower, upper, (f - lower as f32).clamp(0.0, 1.0))
+}
+
+pub fn equalize(image: &mut Image, tile: u32) {
+    let tiles_x = image.width().div_ceil(tile) as usize;
+    let tiles_y = image.height().div_ceil(tile) as usize;
+
+    let curves: Vec<_> = (0..tiles_x * tiles_y)
+        .into_par_iter()
+        .map(|i| {
+            let x = (i % tiles_x) as u32 * tile;
+            let y = (i / tiles_x) as u32 * tile;
+            tile_curve(image, x, y, tile)
+        })
+        .collect();
+
+    let width = image.width();
+    image
+        .par_chunks_mut(width as usize * 2)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let (ty0, ty1, ay) = neighbours(y as u32, tile, tiles_y);
+
+            for (x, pixel) in row.chunks_exact_mut(2).enumerate() {
+                let (tx0, tx1, ax) = neighbours(x as u32, tile, tiles_x);
+                let bin = (pixel[0] >> BIN_SHIFT) as usize;
+                let map = |tx: usize, ty: usize| curves[ty * tiles_x + tx][bin] as f32;
+
+                let top = map(tx0, ty0) * (1.0 - ax) + map(tx1, ty0) * ax;
+                let bottom = map(tx0, ty1) * (1.0 - ax) + map(tx1, ty1) * ax;
+                pixel[0] = (top * (1.0 - ay) + bottom * ay) as u16;
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(image: &Image, columns: std::ops::Range<u32>) -> u16 {
+        let values: Vec<_> = columns
+            .flat_map(|x| (0..image.height()).map(move |y| (x, y)))
+            .map(|(x, y)| image.get_pixel(x, y)[0])
+            .collect();
+        values.iter().max().unwrap() - values.iter().min().unwrap()
+    }
+
+    #[test]
+    fn stretches_both_scales() {
+        // A dim ramp on the left, and the same ramp much brighter on the
+        // right. The outer quarters only use their own tile's curve.
+        let mut image = Image::from_fn(256, 128, |x, y| {
+            let ramp = (x % 128) as u16 * 24 + y as u16 * 7;
+            let base = if x < 128 { 0 } else { 40000 };
+            image::LumaA([base + ramp, u16::MAX])
+        });
+        let dim = range(&image, 0..64);
+        let bright = range(&image, 192..256);
+
+        equalize(&mut image, 128);
+        assert!(range(&image, 0..64) > 3 * dim);
+        assert!(range(&image, 192..256) > 3 * bright);
+    }
+}
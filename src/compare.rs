@@ -0,0 +1,138 @@
+//! The --compare regression check, which diffs a finished render against a
+//! saved reference image.
+
+use image::{GrayImage, Luma, RgbaImage};
+
+use crate::Args;
+
+struct Difference {
+    /// Mean absolute difference over every channel of every pixel, from 0 to
+    /// 255.
+    mean: f64,
+    max: u8,
+    /// Each pixel's largest channel difference.
+    per_pixel: Vec<u8>,
+}
+
+fn difference(image: &RgbaImage, reference: &RgbaImage) -> Difference {
+    let mut total = 0_u64;
+    let per_pixel: Vec<u8> = image
+        .pixels()
+        .zip(reference.pixels())
+        .map(|(a, b)| {
+            let channels = a.0.iter().zip(&b.0).map(|(&a, &b)| a.abs_diff(b));
+            channels.fold(0, |max, d| {
+                total += d as u64;
+                max.max(d)
+            })
+        })
+        .collect();
+
+    Difference {
+        mean: total as f64 / (4 * per_pixel.len()).max(1) as f64,
+        max: per_pixel.iter().copied().max().unwrap_or(0),
+        per_pixel,
+    }
+}
+
+/// Writes the per-pixel difference as greyscale, stretched so the largest
+/// difference is white.
+fn save_difference(diff: &Difference, (w, h): (u32, u32), path: &str) -> Result<(), String> {
+    let scale = 255.0 / diff.max.max(1) as f32;
+    let image = GrayImage::from_fn(w, h, |x, y| {
+        let d = diff.per_pixel[(y * w + x) as usize];
+        Luma([(d as f32 * scale).round() as u8])
+    });
+
+    image
+        .save(path)
+        .map_err(|e| format!("Couldn't save difference '{}': {}", path, e))
+}
+
+/// Compares the render against the reference, printing the error. Returns
+/// whether the mean error is within --tolerance.
+pub fn check(image: &RgbaImage, reference: &str, args: &Args) -> Result<bool, String> {
+    let reference = image::open(reference)
+        .map_err(|e| format!("Couldn't open reference '{}': {}", reference, e))?
+        .to_rgba8();
+    if reference.dimensions() != image.dimensions() {
+        return Err(format!(
+            "The reference is {}x{}, but the render is {}x{}",
+            reference.width(),
+            reference.height(),
+            image.width(),
+            image.height()
+        ));
+    }
+
+    let diff = difference(image, &reference);
+    let differing = diff.per_pixel.iter().filter(|&&d| d > 0).count();
+    eprintln!(
+        "Compared to reference: mean error {:.4}, max error {}, {} of {} pixels differ",
+        diff.mean,
+        diff.max,
+        differing,
+        diff.per_pixel.len()
+    );
+
+    if let Some(path) = &args.diff_out {
+        save_difference(&diff, image.dimensions(), path)?;
+    }
+
+    Ok(diff.mean <= args.tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::tests::args;
+
+    #[test]
+    fn measures_the_difference() {
+        let reference = RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255]));
+        let mut image = reference.clone();
+        image.put_pixel(1, 0, Rgba([14, 20, 22, 255]));
+
+        let diff = difference(&image, &reference);
+        assert_eq!(diff.per_pixel, [0, 8, 0, 0]);
+        assert_eq!(diff.max, 8);
+        assert_eq!(diff.mean, 12.0 / 16.0);
+    }
+
+    #[test]
+    fn checks_against_a_saved_reference() {
+        let dir = std::env::temp_dir();
+        let reference = dir.join("mandeltrace-compare-reference.png");
+        let diff_out = dir.join("mandeltrace-compare-diff.png");
+        let (reference, diff_out) = (reference.to_str().unwrap(), diff_out.to_str().unwrap());
+        let saved = RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255]));
+        saved.save(reference).unwrap();
+
+        let mut image = saved.clone();
+        image.put_pixel(0, 1, Rgba([10, 20, 34, 255]));
+        assert_eq!(check(&saved, reference, &args(&[])), Ok(true));
+        assert_eq!(check(&image, reference, &args(&[])), Ok(false));
+        let lenient = [
+            "--tolerance",
+            "0.25",
+            "--compare",
+            reference,
+            "--diff-out",
+            diff_out,
+        ];
+        let lenient = args(&lenient);
+        assert_eq!(check(&image, reference, &lenient), Ok(true));
+
+        let written = image::open(diff_out).unwrap().to_luma8();
+        assert_eq!(written.into_raw(), [0, 0, 255, 0]);
+
+        let small = RgbaImage::new(1, 2);
+        assert!(check(&small, reference, &args(&[]))
+            .unwrap_err()
+            .starts_with("The reference is 2x2"));
+        std::fs::remove_file(reference).unwrap();
+        std::fs::remove_file(diff_out).unwrap();
+    }
+}
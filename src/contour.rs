@@ -0,0 +1,164 @@
+//! The --contour line art output, which traces iso-density lines through the
+//! finished traces by marching squares, like the contours of a map.
+
+use std::str::FromStr;
+
+use image::{ImageBuffer, Luma, Rgba, RgbaImage};
+use imageproc::{drawing::draw_line_segment_mut, filter::gaussian_blur_f32};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{palette, Args, Image};
+
+/// The colour contour lines are drawn in, as `#rrggbb`.
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct LineColour([u8; 3]);
+
+impl FromStr for LineColour {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        palette::parse_hex(s.trim())
+            .map(|rgb| Self(rgb.map(|v| v as u8)))
+            .ok_or_else(|| format!("Expected a #rrggbb colour: '{}'", s))
+    }
+}
+
+/// The traces are blurred by this sigma, in pixels, before contouring, so
+/// pixel noise in sparse areas doesn't break up into specks.
+const SMOOTHING: f32 = 2.0;
+
+type Point = (f32, f32);
+type Levels = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// The contour segments at `level` through the square of pixel centres with
+/// its top left corner at `(x, y)`.
+fn cell_segments(image: &Levels, x: u32, y: u32, level: f32) -> Vec<(Point, Point)> {
+    let value = |x: u32, y: u32| image.get_pixel(x, y)[0] as f32;
+    let (tl, tr) = (value(x, y), value(x + 1, y));
+    let (bl, br) = (value(x, y + 1), value(x + 1, y + 1));
+
+    // Where the level crosses between two corners, from 0 at `a` to 1 at `b`.
+    let cross = |a: f32, b: f32| (level - a) / (b - a);
+    let (x, y) = (x as f32, y as f32);
+    let top = || (x + cross(tl, tr), y);
+    let right = || (x + 1.0, y + cross(tr, br));
+    let bottom = || (x + cross(bl, br), y + 1.0);
+    let left = || (x, y + cross(tl, bl));
+
+    let above = |v: f32| v > level;
+    let case =
+        (above(tl) as u8) << 3 | (above(tr) as u8) << 2 | (above(br) as u8) << 1 | above(bl) as u8;
+    // The two saddles are split by whether the centre is above the level.
+    let centre_above = above((tl + tr + bl + br) / 4.0);
+    match case {
+        1 | 14 => vec![(left(), bottom())],
+        2 | 13 => vec![(bottom(), right())],
+        3 | 12 => vec![(left(), right())],
+        4 | 11 => vec![(top(), right())],
+        6 | 9 => vec![(top(), bottom())],
+        7 | 8 => vec![(top(), left())],
+        5 if centre_above => vec![(top(), left()), (bottom(), right())],
+        5 => vec![(top(), right()), (left(), bottom())],
+        10 if centre_above => vec![(top(), right()), (left(), bottom())],
+        10 => vec![(top(), left()), (bottom(), right())],
+        _ => Vec::new(),
+    }
+}
+
+/// Draws `levels` contours, evenly spaced between black and the brightest
+/// pixel of `traces`, over `base` or the plain background.
+pub fn draw(traces: &Image, levels: u32, base: Option<RgbaImage>, args: &Args) -> RgbaImage {
+    let (w, h) = traces.dimensions();
+    let luma: Levels = ImageBuffer::from_fn(w, h, |x, y| Luma([traces.get_pixel(x, y)[0]]));
+    let luma = &gaussian_blur_f32(&luma, SMOOTHING);
+    let max = luma.pixels().map(|p| p[0]).max().unwrap_or(0) as f32;
+    let [r, g, b] = args.contour_colour.map_or([255; 3], |c| c.0);
+
+    let segments: Vec<_> = (1..=levels)
+        .flat_map(|k| {
+            let level = max * k as f32 / (levels + 1) as f32;
+            (0..h.saturating_sub(1)).map(move |y| (level, y))
+        })
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .flat_map_iter(|(level, y)| {
+            (0..w.saturating_sub(1)).flat_map(move |x| cell_segments(luma, x, y, level))
+        })
+        .collect();
+
+    let mut out = base.unwrap_or_else(|| {
+        let alpha = if args.transparent_bg { 0 } else { 255 };
+        RgbaImage::from_pixel(w, h, Rgba([0, 0, 0, alpha]))
+    });
+    for (from, to) in segments {
+        draw_line_segment_mut(&mut out, from, to, Rgba([r, g, b, 255]));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use image::LumaA;
+
+    use super::*;
+    use crate::tests::args;
+
+    fn cell(corners: [[u16; 2]; 2]) -> Levels {
+        ImageBuffer::from_fn(2, 2, |x, y| Luma([corners[y as usize][x as usize]]))
+    }
+
+    #[test]
+    fn marches_squares() {
+        // Only the top right corner is above the level.
+        let corner = cell([[0, 100], [0, 0]]);
+        assert_eq!(
+            cell_segments(&corner, 0, 0, 50.0),
+            [((0.5, 0.0), (1.0, 0.5))]
+        );
+
+        // Left above, right below: a vertical line a quarter of the way across.
+        let edge = cell([[100, 0], [100, 0]]);
+        assert_eq!(
+            cell_segments(&edge, 0, 0, 75.0),
+            [((0.25, 0.0), (0.25, 1.0))]
+        );
+
+        assert!(cell_segments(&cell([[10; 2]; 2]), 0, 0, 50.0).is_empty());
+        assert!(cell_segments(&cell([[90; 2]; 2]), 0, 0, 50.0).is_empty());
+
+        // Saddles split into two segments either way.
+        let saddle = cell([[100, 0], [0, 100]]);
+        assert_eq!(cell_segments(&saddle, 0, 0, 40.0).len(), 2);
+        assert_eq!(cell_segments(&saddle, 0, 0, 60.0).len(), 2);
+    }
+
+    #[test]
+    fn draws_lines_around_a_bright_spot() {
+        let args = args(&["--contour", "1", "--contour-colour", "#ff0000"]);
+        let traces = Image::from_fn(32, 32, |x, y| {
+            let r = (x as f32 - 15.5).hypot(y as f32 - 15.5);
+            LumaA([if r < 8.0 { u16::MAX } else { 0 }, u16::MAX])
+        });
+        let out = draw(&traces, 1, None, &args);
+
+        let red = Rgba([255, 0, 0, 255]);
+        assert_eq!(out.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(out.get_pixel(15, 15), &Rgba([0, 0, 0, 255]));
+        let ring: Vec<_> = out
+            .enumerate_pixels()
+            .filter(|(_, _, p)| **p == red)
+            .collect();
+        assert!(ring.len() > 30);
+        assert!(ring.iter().all(|&(x, y, _)| {
+            let r = (x as f32 - 15.5).hypot(y as f32 - 15.5);
+            (5.0..11.0).contains(&r)
+        }));
+    }
+
+    #[test]
+    fn parses_line_colours() {
+        assert_eq!(" #10ff00".parse::<LineColour>().unwrap().0, [16, 255, 0]);
+        assert!("red".parse::<LineColour>().is_err());
+    }
+}
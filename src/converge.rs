@@ -0,0 +1,154 @@
+//! The --target-quality loop, which renders the grid again and again with
+//! fresh random jitter and averages the batches, until another batch barely
+//! changes the result.
+
+use std::sync::atomic::Ordering;
+
+use image::Luma;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+
+use crate::{cell_random, depth, progress::ProgressFile, Args, Canvas, INTERRUPTED};
+
+/// Stop after this many batches even if the change is still too large.
+const MAX_BATCHES: usize = 64;
+
+/// The average brightness of each pixel over the batches so far, from 0 to
+/// 1, as drawn over black.
+fn coverage(sums: &[f64], batches: usize) -> Vec<f64> {
+    sums.iter().map(|s| s / batches as f64).collect()
+}
+
+pub fn render(
+    coords: &[(f64, f64)],
+    target: f64,
+    args: &Args,
+    bar: &ProgressBar,
+    progress_file: Option<&ProgressFile>,
+) -> Canvas {
+    let batch_args = Args {
+        target_quality: None,
+        ..args.clone()
+    };
+    let chunks = coords.len().div_ceil(args.work_chunk_len()) as u64;
+    let (delta_re, delta_im) = args.grid_spacing();
+
+    let mut sums = vec![0.0; args.size as usize * args.size as usize];
+    let mut colour = None;
+    let mut previous: Option<Vec<f64>> = None;
+    let mut batches = 0;
+    let mut settled = false;
+    while batches < MAX_BATCHES && !INTERRUPTED.load(Ordering::SeqCst) {
+        if batches > 0 {
+            bar.inc_length(chunks);
+        }
+
+        // Each sample moves to a random point in the cell after it, so the
+        // batches together cover the grid cells evenly.
+        let jittered: Vec<_> = coords
+            .par_iter()
+            .enumerate()
+            .map(|(i, &(x, y))| {
+                (
+                    x + cell_random(args.seed, i, batches, 0) * delta_re,
+                    y + cell_random(args.seed, i, batches, 1) * delta_im,
+                )
+            })
+            .collect();
+        let canvas = crate::render(&jittered, &batch_args, bar, progress_file);
+        batches += 1;
+
+        let max = u16::MAX as f64;
+        sums.iter_mut()
+            .zip(canvas.image().pixels())
+            .for_each(|(s, p)| *s += p[0] as f64 * p[1] as f64 / (max * max));
+        // Colour sums are normalized per pixel, so they're simply added.
+        match (&mut colour, canvas.colour) {
+            (None, batch) => colour = batch,
+            (Some(total), Some(batch)) => {
+                total
+                    .iter_mut()
+                    .zip(batch)
+                    .for_each(|(t, b): (&mut [f32; 4], _)| {
+                        for c in 0..4 {
+                            t[c] += b[c];
+                        }
+                    })
+            }
+            (Some(_), None) => {}
+        }
+
+        let current = coverage(&sums, batches);
+        if let Some(previous) = &previous {
+            let change = current
+                .iter()
+                .zip(previous)
+                .map(|(a, b)| (a - b).abs())
+                .sum::<f64>()
+                / current.len() as f64;
+            bar.println(format!("Batch {}: mean change {:.6}", batches, change));
+            if change <= target {
+                settled = true;
+                break;
+            }
+        }
+        previous = Some(current);
+    }
+
+    if !settled && batches == MAX_BATCHES {
+        eprintln!(
+            "Warning: stopped after {} batches without reaching the target quality",
+            MAX_BATCHES
+        );
+    }
+    eprintln!(
+        "Settled on {} samples in {} batches",
+        coords.len() * batches,
+        batches
+    );
+
+    // Averaging the brightness over black, rather than blending the batches,
+    // keeps the result as bright as one batch.
+    let mut canvas = Canvas::new(args);
+    canvas
+        .density
+        .pixels_mut()
+        .zip(coverage(&sums, batches.max(1)))
+        .for_each(|(p, c)| *p = Luma([depth((c * u16::MAX as f64) as f32)]));
+    canvas.colour = colour;
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sample_grid, tests::args};
+
+    fn mean_alpha(canvas: &Canvas) -> f64 {
+        let image = canvas.image();
+        image.pixels().map(|p| p[1] as f64).sum::<f64>() / image.len() as f64
+    }
+
+    #[test]
+    fn averages_batches_at_one_batch_brightness() {
+        assert_eq!(coverage(&[3.0, 1.5], 3), [1.0, 0.5]);
+
+        let args = args(&[
+            "-s", "64", "-z", "16", "-d", "0.05", "-l", "50", "-m", "Escaped",
+        ]);
+        let grid = sample_grid(&args);
+        let bar = ProgressBar::hidden();
+        let single = crate::render(&grid, &args, &bar, None);
+        // Any change is small enough, so this stops after the second batch.
+        let averaged = render(&grid, 1.0, &args, &bar, None);
+
+        let (single, averaged) = (mean_alpha(&single), mean_alpha(&averaged));
+        assert!(averaged > 0.0);
+        assert!(
+            (averaged / single - 1.0).abs() < 0.2,
+            "{} vs {}",
+            averaged,
+            single
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! The --deepzoom-out pyramid, which cuts the finished render into 256x256
+//! tiles at every zoom level of the Deep Zoom (DZI) format, for browser
+//! viewers like OpenSeadragon.
+//!
+//! The render is the most detailed level, and each level below is the one
+//! above halved, rounding up, down to a single pixel. The levels are
+//! downsampled rather than traced again at their own size, because a trace's
+//! brightness depends on how many samples land in each pixel, so a fresh
+//! render of each level would be exposed differently from its neighbours.
+//!
+//! Next to `<name>.dzi`, the tiles are written to
+//! `<name>_files/<level>/<column>_<row>.png`, with no overlap between them.
+
+use std::{fs, path::Path};
+
+use image::{imageops, RgbaImage};
+
+const TILE_SIZE: u32 = 256;
+
+fn descriptor(width: u32, height: u32) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" ",
+            "Format=\"png\" Overlap=\"0\" TileSize=\"{}\">\n",
+            "  <Size Width=\"{}\" Height=\"{}\"/>\n",
+            "</Image>\n"
+        ),
+        TILE_SIZE, width, height
+    )
+}
+
+fn write_tiles(level: &RgbaImage, dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Couldn't create '{}': {}", dir.display(), e))?;
+
+    let (w, h) = level.dimensions();
+    for row in 0..h.div_ceil(TILE_SIZE) {
+        for column in 0..w.div_ceil(TILE_SIZE) {
+            let (x, y) = (column * TILE_SIZE, row * TILE_SIZE);
+            let tile = imageops::crop_imm(level, x, y, TILE_SIZE.min(w - x), TILE_SIZE.min(h - y))
+                .to_image();
+
+            let path = dir.join(format!("{}_{}.png", column, row));
+            tile.save(&path)
+                .map_err(|e| format!("Couldn't save tile '{}': {}", path.display(), e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `<dir>/<name>.dzi` and the tiles of every level of `image`.
+pub fn write(image: &RgbaImage, dir: &str, name: &str) -> Result<(), String> {
+    let dir = Path::new(dir);
+    let (width, height) = image.dimensions();
+    // The smallest level is a single pixel.
+    let top = 32 - (width.max(height) - 1).leading_zeros();
+
+    let tiles = dir.join(format!("{}_files", name));
+    let mut level = image.clone();
+    for n in (0..=top).rev() {
+        write_tiles(&level, &tiles.join(n.to_string()))?;
+        if n > 0 {
+            let (w, h) = level.dimensions();
+            level = imageops::resize(
+                &level,
+                w.div_ceil(2),
+                h.div_ceil(2),
+                imageops::FilterType::Triangle,
+            );
+        }
+    }
+
+    let path = dir.join(format!("{}.dzi", name));
+    fs::write(&path, descriptor(width, height))
+        .map_err(|e| format!("Couldn't write '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_every_level() {
+        let dir = std::env::temp_dir().join("mandeltrace-deepzoom");
+        let image = RgbaImage::from_pixel(600, 300, image::Rgba([200, 100, 50, 255]));
+        write(&image, dir.to_str().unwrap(), "view").unwrap();
+
+        let descriptor = fs::read_to_string(dir.join("view.dzi")).unwrap();
+        assert!(descriptor.contains("<Size Width=\"600\" Height=\"300\"/>"));
+
+        let level = |n: u32| dir.join("view_files").join(n.to_string());
+        let tiles = |n: u32| fs::read_dir(level(n)).unwrap().count();
+        // 600x300, then 300x150, and so on down to 2x1 and 1x1 at level 0.
+        assert_eq!((tiles(10), tiles(9), tiles(8), tiles(0)), (6, 2, 1, 1));
+        assert!(!level(11).exists());
+
+        let corner = image::open(level(10).join("2_1.png")).unwrap();
+        assert_eq!(image::GenericImageView::dimensions(&corner), (88, 44));
+        let single = image::open(level(0).join("0_0.png")).unwrap();
+        assert_eq!(image::GenericImageView::dimensions(&single), (1, 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
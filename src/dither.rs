@@ -0,0 +1,126 @@
+//! Reduces the 16-bit luma canvas to 8 bits, optionally dithering to hide
+//! banding in smooth gradients.
+
+use serde::Serialize;
+
+use std::str::FromStr;
+
+use crate::Image;
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    None,
+    Ordered,
+    ErrorDiffusion,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("none") {
+            Ok(Self::None)
+        } else if s.eq_ignore_ascii_case("ordered") {
+            Ok(Self::Ordered)
+        } else if s.eq_ignore_ascii_case("error-diffusion") {
+            Ok(Self::ErrorDiffusion)
+        } else {
+            Err(format!("Unknown dither mode: '{}'", s))
+        }
+    }
+}
+
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Quantizes the luma channel of `image` to one byte per pixel, in row-major
+/// order.
+pub fn quantize(image: &Image, dither: Dither) -> Vec<u8> {
+    match dither {
+        Dither::None => image.pixels().map(|p| (p[0] >> 8) as u8).collect(),
+        Dither::Ordered => image
+            .enumerate_pixels()
+            .map(|(x, y, p)| {
+                let threshold = (BAYER[y as usize % 4][x as usize % 4] as f32 + 0.5) / 16.0;
+                (p[0] as f32 / 256.0 + threshold).min(255.0) as u8
+            })
+            .collect(),
+        Dither::ErrorDiffusion => floyd_steinberg(image),
+    }
+}
+
+fn floyd_steinberg(image: &Image) -> Vec<u8> {
+    let width = image.width() as usize;
+    let mut out = Vec::with_capacity(width * image.height() as usize);
+
+    // Error carried into the current and next rows, padded by one pixel on
+    // each side so the kernel never needs bounds checks.
+    let mut current = vec![0.0_f32; width + 2];
+    let mut next = vec![0.0_f32; width + 2];
+
+    for row in image.rows() {
+        for (x, p) in row.enumerate() {
+            let target = p[0] as f32 / 256.0 + current[x + 1];
+            let quantized = target.round().clamp(0.0, 255.0);
+            let error = target - quantized;
+            out.push(quantized as u8);
+
+            current[x + 2] += error * 7.0 / 16.0;
+            next[x] += error * 3.0 / 16.0;
+            next[x + 1] += error * 5.0 / 16.0;
+            next[x + 2] += error * 1.0 / 16.0;
+        }
+
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|e| *e = 0.0);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean(values: &[u8]) -> f64 {
+        values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64
+    }
+
+    #[test]
+    fn dithering_keeps_the_mean_level() {
+        // Halfway between the 8-bit levels 100 and 101.
+        let image = Image::from_pixel(32, 32, image::LumaA([100 * 256 + 128, u16::MAX]));
+
+        assert!(quantize(&image, Dither::None).iter().all(|&v| v == 100));
+        for &dither in &[Dither::Ordered, Dither::ErrorDiffusion] {
+            let out = quantize(&image, dither);
+            assert!(out.iter().all(|&v| v == 100 || v == 101), "{:?}", dither);
+            assert!((mean(&out) - 100.5).abs() < 0.02, "{:?}", dither);
+        }
+    }
+
+    #[test]
+    fn extremes_stay_in_range() {
+        for &level in &[0, u16::MAX] {
+            let image = Image::from_pixel(8, 8, image::LumaA([level, u16::MAX]));
+            for &dither in &[Dither::None, Dither::Ordered, Dither::ErrorDiffusion] {
+                let expected = (level >> 8) as u8;
+                assert!(quantize(&image, dither).iter().all(|&v| v == expected));
+            }
+        }
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert!(matches!(" Ordered ".parse(), Ok(Dither::Ordered)));
+        assert!(matches!(
+            "error-diffusion".parse(),
+            Ok(Dither::ErrorDiffusion)
+        ));
+        assert_eq!(
+            "bayer".parse::<Dither>().unwrap_err(),
+            "Unknown dither mode: 'bayer'"
+        );
+    }
+}
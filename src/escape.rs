@@ -0,0 +1,317 @@
+//! The --escape-shape bailout regions, which decide when an orbit has
+//! escaped.
+//!
+//! Each shape has a size, `r`: the half-width of the square, the radius of
+//! the circle, the distance from the centre to a corner of the diamond, or
+//! the scale a --escape-polygon is drawn at. Orbits escape the shape at -b.
+//! The overlay and --clip-to-set escape it at whatever size just holds the
+//! radius 2 circle, so they still find the set itself.
+
+use std::{fmt, fs, str::FromStr};
+
+use num::{complex::Complex64, Complex, Float};
+use serde::Serialize;
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EscapeShape {
+    Square,
+    Circle,
+    Diamond,
+    Custom,
+}
+
+impl FromStr for EscapeShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("square") {
+            Ok(Self::Square)
+        } else if s.eq_ignore_ascii_case("circle") {
+            Ok(Self::Circle)
+        } else if s.eq_ignore_ascii_case("diamond") {
+            Ok(Self::Diamond)
+        } else if s.eq_ignore_ascii_case("custom") {
+            Ok(Self::Custom)
+        } else {
+            Err(format!("Unknown escape shape: '{}'", s))
+        }
+    }
+}
+
+/// A closed polygon around the origin, at a size of 1.
+pub struct Polygon {
+    vertices: Vec<Complex64>,
+    /// The distance from the origin to the nearest edge.
+    inradius: f64,
+}
+
+impl fmt::Debug for Polygon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Polygon({} vertices)", self.vertices.len())
+    }
+}
+
+impl Polygon {
+    /// Loads `re im` vertices, one per line, in order around the polygon.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read escape polygon '{}': {}", path, e))?;
+
+        let mut vertices = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values: Vec<_> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|v| !v.is_empty())
+                .map(str::parse::<f64>)
+                .collect();
+            match values.as_slice() {
+                [Ok(re), Ok(im)] if re.is_finite() && im.is_finite() => {
+                    vertices.push(Complex64::new(*re, *im))
+                }
+                _ => {
+                    return Err(format!(
+                        "Escape polygon '{}' line {}: expected 're im'",
+                        path,
+                        n + 1
+                    ))
+                }
+            }
+        }
+
+        if vertices.len() < 3 {
+            return Err(format!(
+                "Escape polygon '{}' needs at least three vertices",
+                path
+            ));
+        }
+
+        let mut polygon = Self {
+            vertices,
+            inradius: 0.0,
+        };
+        polygon.inradius = polygon
+            .edges()
+            .map(|(a, b)| distance_to_segment(Complex64::default(), a, b))
+            .fold(f64::INFINITY, f64::min);
+        if !polygon.contains(Complex64::default()) || polygon.inradius == 0.0 {
+            return Err(format!(
+                "Escape polygon '{}' must have the origin inside it",
+                path
+            ));
+        }
+
+        Ok(polygon)
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (Complex64, Complex64)> + '_ {
+        let next = self.vertices.iter().cycle().skip(1);
+        self.vertices.iter().copied().zip(next.copied())
+    }
+
+    /// Even-odd test, by counting the edges a ray along +re crosses.
+    fn contains(&self, z: Complex64) -> bool {
+        self.edges()
+            .filter(|&(a, b)| {
+                (a.im > z.im) != (b.im > z.im)
+                    && z.re < a.re + (z.im - a.im) / (b.im - a.im) * (b.re - a.re)
+            })
+            .count()
+            % 2
+            == 1
+    }
+}
+
+fn distance_to_segment(z: Complex64, a: Complex64, b: Complex64) -> f64 {
+    let ab = b - a;
+    let t = if ab.norm_sqr() == 0.0 {
+        0.0
+    } else {
+        (((z - a) * ab.conj()).re / ab.norm_sqr()).clamp(0.0, 1.0)
+    };
+    (a + ab * t - z).norm()
+}
+
+/// An escape shape, with its polygon for --escape-shape custom.
+#[derive(Clone, Copy)]
+pub enum Bailout<'a> {
+    Square,
+    Circle,
+    Diamond,
+    Polygon(&'a Polygon),
+}
+
+impl<'a> Bailout<'a> {
+    pub fn new(shape: EscapeShape, polygon: Option<&'a Polygon>) -> Self {
+        match shape {
+            EscapeShape::Square => Self::Square,
+            EscapeShape::Circle => Self::Circle,
+            EscapeShape::Diamond => Self::Diamond,
+            EscapeShape::Custom => {
+                Self::Polygon(polygon.expect("validated to have a polygon with custom"))
+            }
+        }
+    }
+
+    /// Whether `z` is outside the shape at size `r`. NaN is never outside.
+    pub fn outside<T: Float>(self, z: Complex<T>, r: T) -> bool {
+        match self {
+            Self::Square => z.im.abs() > r || z.re.abs() > r,
+            Self::Circle => z.norm_sqr() > r * r,
+            Self::Diamond => z.re.abs() + z.im.abs() > r,
+            Self::Polygon(polygon) => {
+                let to_f64 = |v: T| v.to_f64().unwrap();
+                let z = Complex64::new(to_f64(z.re), to_f64(z.im)) / to_f64(r);
+                z.norm() > polygon.inradius && !polygon.contains(z)
+            }
+        }
+    }
+
+    /// The distance from the centre to the nearest edge at a size of 1.
+    pub fn inradius(self) -> f64 {
+        match self {
+            Self::Square | Self::Circle => 1.0,
+            Self::Diamond => std::f64::consts::FRAC_1_SQRT_2,
+            Self::Polygon(polygon) => polygon.inradius,
+        }
+    }
+
+    /// The point where the segment from `inside` to `outside` first leaves
+    /// the shape at size `r`. If `inside` is already outside, `outside` is
+    /// returned.
+    pub fn crossing(self, inside: Complex64, outside: Complex64, r: f64) -> Complex64 {
+        if self.outside(inside, r) {
+            return outside;
+        }
+
+        if let Self::Square = self {
+            // The fraction of the way along the segment where each
+            // coordinate reaches the edge, if it gets there.
+            let crossing = |from: f64, to: f64| {
+                if to.abs() > r {
+                    (r.copysign(to) - from) / (to - from)
+                } else {
+                    1.0
+                }
+            };
+            let t = crossing(inside.re, outside.re).min(crossing(inside.im, outside.im));
+            return inside + (outside - inside) * t;
+        }
+
+        // The other shapes are found by bisection, to well under a pixel. Past
+        // a concave corner this may find a later crossing than the first.
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..48 {
+            let mid = 0.5 * (lo + hi);
+            if self.outside(inside + (outside - inside) * mid, r) {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        inside + (outside - inside) * hi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn z(re: f64, im: f64) -> Complex64 {
+        Complex64::new(re, im)
+    }
+
+    /// Writes `text` to a file named after the test, and loads it.
+    fn load(name: &str, text: &str) -> Result<Polygon, String> {
+        let path = std::env::temp_dir().join(format!("mandeltrace-{}.txt", name));
+        fs::write(&path, text).unwrap();
+        let polygon = Polygon::load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        polygon
+    }
+
+    fn close(a: Complex64, b: Complex64) -> bool {
+        (a - b).norm() < 1e-9
+    }
+
+    #[test]
+    fn square() {
+        let shape = Bailout::Square;
+        assert!(!shape.outside(z(1.9, -1.9), 2.0));
+        assert!(shape.outside(z(2.1, 0.0), 2.0));
+        assert!(shape.outside(z(0.0, -2.1), 2.0));
+        assert!(!shape.outside(z(f64::NAN, 0.0), 2.0));
+        assert_eq!(shape.inradius(), 1.0);
+        assert!(close(
+            shape.crossing(z(0.0, 0.0), z(4.0, 1.0), 2.0),
+            z(2.0, 0.5)
+        ));
+        assert!(close(
+            shape.crossing(z(1.0, 1.0), z(1.0, -5.0), 2.0),
+            z(1.0, -2.0)
+        ));
+    }
+
+    #[test]
+    fn circle() {
+        let shape = Bailout::Circle;
+        assert!(!shape.outside(z(1.4, 1.4), 2.0));
+        assert!(shape.outside(z(1.5, 1.5), 2.0));
+        assert_eq!(shape.inradius(), 1.0);
+        let crossing = shape.crossing(z(0.0, 0.0), z(3.0, 4.0), 2.0);
+        assert!(close(crossing, z(1.2, 1.6)));
+    }
+
+    #[test]
+    fn diamond() {
+        let shape = Bailout::Diamond;
+        assert!(!shape.outside(z(0.9, -1.0), 2.0));
+        assert!(shape.outside(z(1.1, -1.0), 2.0));
+        assert!(!shape.outside(z(0.0, 2.0), 2.0));
+        assert!((shape.inradius() - 0.5_f64.sqrt()).abs() < 1e-12);
+        let crossing = shape.crossing(z(0.0, 0.0), z(2.0, 2.0), 2.0);
+        assert!(close(crossing, z(1.0, 1.0)));
+    }
+
+    #[test]
+    fn custom_polygon() {
+        // A square of half-width 1 with a notch cut into its right side,
+        // reaching in to re = 0.5.
+        let notched = load(
+            "notched",
+            "# notched square\n-1 -1\n1 -1\n1 -0.2\n0.5 0\n1 0.2\n\n1 1\n-1 1\n",
+        )
+        .unwrap();
+        let shape = Bailout::Polygon(&notched);
+        assert!((shape.inradius() - 0.5).abs() < 1e-12);
+        assert!(!shape.outside(z(1.8, -1.8), 2.0));
+        assert!(!shape.outside(z(0.9, 0.0), 2.0));
+        assert!(shape.outside(z(1.1, 0.0), 2.0));
+        assert!(shape.outside(z(0.0, 2.1), 2.0));
+        let crossing = shape.crossing(z(0.0, 0.0), z(0.0, 4.0), 2.0);
+        assert!(close(crossing, z(0.0, 2.0)));
+        let crossing = shape.crossing(z(0.0, 0.0), z(2.0, 0.0), 2.0);
+        assert!(close(crossing, z(1.0, 0.0)));
+    }
+
+    #[test]
+    fn polygon_needs_the_origin_inside() {
+        let error = load("off-origin", "1 1\n2 1\n2 2\n1 2\n").unwrap_err();
+        assert!(
+            error.contains("must have the origin inside it"),
+            "{}",
+            error
+        );
+        // The origin on an edge doesn't count either.
+        assert!(load("on-edge", "0 -1\n1 0\n0 1\n0 0\n").is_err());
+        assert!(load("too-few", "-1 -1\n1 1\n").is_err());
+        assert!(load("bad-line", "-1 -1\n1 -1\n1\n").is_err());
+    }
+}
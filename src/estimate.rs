@@ -0,0 +1,115 @@
+//! Render time prediction for --estimate.
+//!
+//! A random sample of the grid is traced and drawn on one thread, in a few
+//! separately timed batches. The spread between the batches gives the range
+//! printed alongside the estimate. Only orbit tracing is predicted, not the
+//! overlay or saving the image.
+
+use std::time::Instant;
+
+use crate::{hash, iterate_chunk, would_draw, Args, Canvas};
+
+/// How many grid points each pass is probed with.
+const PROBE_POINTS: usize = 2000;
+const BATCHES: usize = 10;
+
+struct Probe {
+    /// Mean time per grid point, in seconds.
+    mean: f64,
+    /// Variance of `mean`.
+    variance: f64,
+    /// How many of the sampled points were drawn.
+    drawn: usize,
+    sampled: usize,
+}
+
+fn probe(coords: &[(f64, f64)], args: &Args) -> Probe {
+    let draw_args = args.scaled_for_render();
+    let draw_args = draw_args.as_ref().unwrap_or(args);
+    let canvas = Canvas::new(draw_args);
+
+    let count = PROBE_POINTS.min(coords.len());
+    let sample: Vec<_> = (0..count)
+        .map(|i| coords[(hash(args.seed ^ hash(i as u64)) % coords.len() as u64) as usize])
+        .collect();
+
+    let times: Vec<f64> = sample
+        .chunks(count.div_ceil(BATCHES))
+        .map(|batch| {
+            let start = Instant::now();
+            iterate_chunk(batch, canvas.clone(), draw_args);
+            start.elapsed().as_secs_f64() / batch.len() as f64
+        })
+        .collect();
+
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    let variance = if times.len() > 1 {
+        times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (times.len() - 1) as f64
+    } else {
+        0.0
+    };
+
+    Probe {
+        mean,
+        variance: variance / times.len() as f64,
+        drawn: sample.iter().filter(|&&c| would_draw(c, args)).count(),
+        sampled: count,
+    }
+}
+
+pub fn print(coords: &[(f64, f64)], passes: &[(Args, Option<[u8; 3]>)]) {
+    if coords.is_empty() {
+        println!("Estimated render time: 0s (no grid points)");
+        return;
+    }
+
+    let threads = rayon::current_num_threads();
+    let probes: Vec<_> = passes.iter().map(|(pass, _)| probe(coords, pass)).collect();
+    let total: f64 = probes.iter().map(|p| p.mean).sum();
+    let variance: f64 = probes.iter().map(|p| p.variance).sum();
+
+    let scale = coords.len() as f64 / threads as f64;
+    let estimate = total * scale;
+    // Two standard errors either side of the mean.
+    let spread = 2.0 * variance.sqrt() * scale;
+
+    println!(
+        "Estimated render time: {:.1}s ({:.1}s to {:.1}s), on {} thread(s)",
+        estimate,
+        (estimate - spread).max(0.0),
+        estimate + spread,
+        threads
+    );
+    let drawn: usize = probes.iter().map(|p| p.drawn).sum();
+    let sampled: usize = probes.iter().map(|p| p.sampled).sum();
+    println!(
+        "Probed {} orbits over {} pass(es), of which {:.0}% are drawn",
+        sampled,
+        passes.len(),
+        100.0 * drawn as f64 / sampled as f64
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sample_grid, tests::args};
+
+    #[test]
+    fn probes_a_sample_of_the_grid() {
+        let args = args(&[
+            "-s", "64", "-z", "16", "-d", "0.05", "-l", "50", "-m", "Escaped",
+        ]);
+        let grid = sample_grid(&args);
+        assert!(grid.len() > PROBE_POINTS);
+
+        let full = probe(&grid, &args);
+        assert_eq!(full.sampled, PROBE_POINTS);
+        assert!(full.drawn > 0 && full.drawn < full.sampled);
+        assert!(full.mean > 0.0 && full.variance >= 0.0);
+
+        // A grid smaller than the probe is sampled once per point.
+        let few = &grid[..50];
+        assert_eq!(probe(few, &args).sampled, 50);
+    }
+}
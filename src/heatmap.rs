@@ -0,0 +1,71 @@
+//! The --heatmap tone map, which colours each pixel by its log density along
+//! a gradient, and draws everything lit at full strength.
+//!
+//! A --palette-file alone maps brightness to colour, so a faint pixel gets
+//! both the low end of the palette and a low opacity, and the palette's
+//! spread follows the brightness curve, which saturates as traces pile up.
+//! Here the density is recovered from the brightness and its logarithm is
+//! stretched between the sparsest and densest lit pixels, so the whole
+//! gradient spans the range of the image and hue alone shows the density.
+
+use image::{Pixel, Rgba, RgbaImage};
+
+use crate::{palette::Gradient, Image};
+
+/// The optical depth behind a brightness from 0 to 1, capped where the
+/// brightness can't be told from full.
+fn depth(v: f64) -> f64 {
+    -(1.0 - v).max(0.5 / u16::MAX as f64).ln()
+}
+
+/// Blends the heatmap of `image` over `out`.
+pub fn paint(out: &mut RgbaImage, image: &Image, gradient: &Gradient) {
+    let max = u16::MAX as f64;
+    let log_depth: Vec<_> = image
+        .pixels()
+        .map(|p| (p[0] > 0).then(|| depth(p[0] as f64 / max).ln()))
+        .collect();
+
+    let lit = log_depth.iter().flatten();
+    let lo = lit.clone().copied().fold(f64::INFINITY, f64::min);
+    let hi = lit.copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = (hi - lo).max(f64::EPSILON);
+
+    out.pixels_mut().zip(log_depth).for_each(|(o, d)| {
+        if let Some(d) = d {
+            let [r, g, b, _] = gradient.sample((d - lo) / range).0;
+            o.blend(&Rgba([r, g, b, 255]));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use image::LumaA;
+
+    use super::*;
+
+    #[test]
+    fn spans_the_gradient_over_lit_pixels() {
+        let gradient = Gradient::new(vec![(0.0, [0.0, 0.0, 255.0]), (1.0, [255.0, 0.0, 0.0])]);
+        let levels = [0, 1000, 8000, 60000];
+        let image = Image::from_fn(4, 1, |x, _| LumaA([levels[x as usize], u16::MAX]));
+        let mut out = RgbaImage::from_pixel(4, 1, Rgba([9, 9, 9, 255]));
+        paint(&mut out, &image, &gradient);
+
+        assert_eq!(out.get_pixel(0, 0), &Rgba([9, 9, 9, 255]));
+        assert_eq!(out.get_pixel(1, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(out.get_pixel(3, 0), &Rgba([255, 0, 0, 255]));
+        let middle = out.get_pixel(2, 0);
+        assert!(middle[0] > 0 && middle[2] > 0);
+    }
+
+    #[test]
+    fn one_level_is_the_start_of_the_gradient() {
+        let gradient = Gradient::heat();
+        let image = Image::from_pixel(2, 2, LumaA([500, u16::MAX]));
+        let mut out = RgbaImage::new(2, 2);
+        paint(&mut out, &image, &gradient);
+        assert!(out.pixels().all(|p| *p == gradient.sample(0.0)));
+    }
+}
@@ -0,0 +1,112 @@
+//! The --info tool, which prints what --record-params saved beside a render
+//! and the command line that reproduces it.
+
+use std::{fs, path::Path};
+
+use serde_json::Value;
+
+/// Quotes an argument for a POSIX shell, if it needs it.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_=.,/+:%@".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Formats a recorded value for display, without the quotes JSON puts around
+/// strings.
+fn show(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_owned(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+pub fn print(path: &str) -> Result<(), String> {
+    let (w, h) =
+        image::image_dimensions(path).map_err(|e| format!("Couldn't open '{}': {}", path, e))?;
+    println!("Image: {} ({}x{})", path, w, h);
+
+    let record_path = Path::new(path).with_extension("json");
+    let record = match fs::read_to_string(&record_path) {
+        Ok(record) => record,
+        Err(_) => {
+            println!(
+                "No recorded parameters: '{}' doesn't exist. Render with --record-params to keep them.",
+                record_path.display()
+            );
+            return Ok(());
+        }
+    };
+    let record: Value = serde_json::from_str(&record)
+        .map_err(|e| format!("Couldn't read '{}': {}", record_path.display(), e))?;
+
+    let args = &record["args"];
+    let arg = |name: &str| show(&args[name]);
+    // The offsets move the view, so the centre is their negation. Adding zero
+    // turns -0 into 0.
+    let centre = |name: &str| args[name].as_f64().map_or(0.0, |offset| -offset + 0.0);
+
+    println!("Version: {}", show(&record["version"]));
+    println!("Centre: {} {:+}i", centre("re_off"), centre("im_off"));
+    println!("Zoom: {}", arg("zoom"));
+    println!("Size: {}", arg("size"));
+    println!(
+        "Sampling: delta {}, bounds {}, limit {}",
+        arg("delta"),
+        arg("bounds"),
+        arg("limit")
+    );
+    println!("Power: {}, mode: {}", arg("pow"), arg("mode"));
+    println!("Samples: {}", show(&record["samples"]));
+    if let Some(secs) = record["duration_secs"].as_f64() {
+        println!("Duration: {:.1}s", secs);
+    }
+    if record["partial"].as_bool() == Some(true) {
+        println!("Partial: the render was stopped early, a rerun will go further");
+    }
+
+    match record["command"].as_array() {
+        Some(command) => {
+            let command: Vec<_> = command.iter().map(|arg| shell_quote(&show(arg))).collect();
+            println!("Command: {}", command.join(" "));
+        }
+        None => println!("Command: not recorded by this version"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_for_the_shell() {
+        assert_eq!(shell_quote("--size=2000"), "--size=2000");
+        assert_eq!(shell_quote("out dir/a.png"), "'out dir/a.png'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn shows_values_without_json_quotes() {
+        assert_eq!(show(&Value::Null), "-");
+        assert_eq!(show(&Value::from("escaped")), "escaped");
+        assert_eq!(show(&Value::from(0.5)), "0.5");
+        assert_eq!(show(&serde_json::json!([1, 2])), "[1,2]");
+    }
+
+    #[test]
+    fn reports_a_missing_image() {
+        let error = print("/nonexistent/mandeltrace.png").unwrap_err();
+        assert!(
+            error.starts_with("Couldn't open '/nonexistent/mandeltrace.png'"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! The --interactive command loop.
+//!
+//! The sample grid is generated once and kept between renders. Moving the
+//! centre or zoom only changes where orbits are drawn, so only the bulb and
+//! boundary filters and the render itself are redone.
+
+use image::RgbaImage;
+use indicatif::ProgressBar;
+
+use std::io::{self, BufRead, Write};
+
+use crate::{build_passes, filter_grid, layers::LayerFile, output, render_image, Args, Crop};
+
+/// How much smaller than --size a preview is.
+const PREVIEW_DIVISOR: u32 = 4;
+
+const HELP: &str = "\
+Commands:
+  center RE IM   Move the centre of the view to RE+IMi
+  zoom Z         Set the zoom, in pixels per unit
+  limit N        Set the iteration limit
+  show           Print the current view
+  preview        Render at a quarter of --size
+  render         Render at full size
+  save PATH      Save the last render
+  help           Print this list
+  quit           Exit";
+
+/// The arguments for a preview render, at a fraction of the size.
+fn preview_args(args: &Args) -> Args {
+    let mut preview = args.clone();
+    preview.size = (args.size / PREVIEW_DIVISOR).max(1);
+    preview.zoom = args.zoom * preview.size as f64 / args.size as f64;
+    preview.crop = args.crop.map(|c| Crop {
+        width: (c.width / PREVIEW_DIVISOR).max(1),
+        height: (c.height / PREVIEW_DIVISOR).max(1),
+        x: c.x / PREVIEW_DIVISOR,
+        y: c.y / PREVIEW_DIVISOR,
+    });
+    preview
+}
+
+fn render(grid: &[(f64, f64)], args: &Args, layer_file: Option<&LayerFile>) -> RgbaImage {
+    let passes = build_passes(args, layer_file);
+    let blend = layer_file.and_then(|f| f.blend).unwrap_or(args.power_blend);
+    let coords = filter_grid(grid.to_vec(), args, &passes);
+
+    let bar = ProgressBar::new((passes.len() * coords.len() / args.work_chunk_len()) as u64);
+    let image = render_image(&coords, &passes, blend, args, &bar, None);
+    bar.finish_and_clear();
+    image
+}
+
+/// Parses the single argument of a command.
+fn parse_one<T: std::str::FromStr>(words: &[&str]) -> Result<T, String> {
+    match words {
+        [value] => value
+            .parse()
+            .map_err(|_| format!("Invalid value: '{}'", value)),
+        _ => Err("Expected one value".to_owned()),
+    }
+}
+
+pub fn run(mut args: Args, grid: Vec<(f64, f64)>, layer_file: Option<&LayerFile>) {
+    eprintln!("{} grid points. Type 'help' for commands.", grid.len());
+    let mut last = render(&grid, &preview_args(&args), layer_file);
+    eprintln!("Rendered preview");
+
+    let stdin = io::stdin();
+    loop {
+        eprint!("> ");
+        io::stderr().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let words: Vec<_> = line.split_whitespace().collect();
+        let (command, rest) = match words.split_first() {
+            Some((command, rest)) => (*command, rest),
+            None => continue,
+        };
+
+        let result = match command {
+            "center" | "centre" => match rest {
+                [re, im] => match (re.parse::<f64>(), im.parse::<f64>()) {
+                    (Ok(re), Ok(im)) => {
+                        args.re_off = -re;
+                        args.im_off = -im;
+                        Ok(())
+                    }
+                    _ => Err("Invalid coordinate".to_owned()),
+                },
+                _ => Err("Expected RE IM".to_owned()),
+            },
+            "zoom" => parse_one::<f64>(rest).and_then(|zoom| {
+                if zoom.is_finite() && zoom > 0.0 {
+                    args.zoom = zoom;
+                    Ok(())
+                } else {
+                    Err("Zoom must be positive".to_owned())
+                }
+            }),
+            "limit" => parse_one::<usize>(rest).and_then(|limit| {
+                if limit > 0 {
+                    args.limit = limit;
+                    Ok(())
+                } else {
+                    Err("Limit must be at least 1".to_owned())
+                }
+            }),
+            "show" => {
+                println!(
+                    "center {} {}, zoom {}, limit {}, size {}",
+                    0.0 - args.re_off,
+                    0.0 - args.im_off,
+                    args.zoom,
+                    args.limit,
+                    args.size
+                );
+                Ok(())
+            }
+            "preview" => {
+                last = render(&grid, &preview_args(&args), layer_file);
+                eprintln!("Rendered preview");
+                Ok(())
+            }
+            "render" => {
+                last = render(&grid, &args, layer_file);
+                eprintln!("Rendered");
+                Ok(())
+            }
+            "save" => match rest {
+                [path] => {
+                    let mut save_args = args.clone();
+                    save_args.image_name = path.to_string();
+                    output::check_options(&save_args)
+                        .and_then(|_| {
+                            output::save_image(&last, path, &save_args).map_err(|e| e.to_string())
+                        })
+                        .map(|_| eprintln!("Saved {}", path))
+                }
+                _ => Err("Expected a path".to_owned()),
+            },
+            "help" => {
+                eprintln!("{}", HELP);
+                Ok(())
+            }
+            "quit" | "exit" => break,
+            _ => Err(format!("Unknown command: '{}'", command)),
+        };
+
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::args;
+
+    #[test]
+    fn previews_show_the_same_view() {
+        let mut view = args(&["-s", "1000", "-z", "400"]);
+        view.crop = Some(Crop {
+            width: 600,
+            height: 2,
+            x: 101,
+            y: 40,
+        });
+        let preview = preview_args(&view);
+        assert_eq!(preview.size, 250);
+        assert_eq!(preview.zoom, 100.0);
+        let crop = preview.crop.unwrap();
+        assert_eq!((crop.width, crop.height, crop.x, crop.y), (150, 1, 25, 10));
+
+        let tiny = preview_args(&args(&["-s", "3"]));
+        assert_eq!(tiny.size, 1);
+    }
+
+    #[test]
+    fn parses_one_value() {
+        assert_eq!(parse_one::<f64>(&["1.5"]), Ok(1.5));
+        assert_eq!(
+            parse_one::<usize>(&["-3"]),
+            Err("Invalid value: '-3'".to_owned())
+        );
+        assert_eq!(
+            parse_one::<usize>(&["1", "2"]),
+            Err("Expected one value".to_owned())
+        );
+        assert_eq!(
+            parse_one::<usize>(&[]),
+            Err("Expected one value".to_owned())
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Inverse iteration (IIM) for --inverse-julia, which draws the Julia set of
+//! a fixed `c` by running `z -> ±sqrt(z - c)` backwards from each grid point.
+//!
+//! The inverse map contracts towards the Julia set, so after a short
+//! transient every step lands on the set. Each step picks one of the two
+//! square roots at random, with equal weight, so the points follow the
+//! set's harmonic measure: tips and outer spirals fill in quickly, while deep
+//! fjords and pinch points are hit very rarely. The modified method (MIIM)
+//! evens this out by pruning branches in pixels that are already dense,
+//! which needs a search tree rather than independent walks, so it isn't done
+//! here. Raising the opacity, -l or the number of grid points brings out the
+//! faint regions instead.
+
+use image::Luma;
+use num::complex::Complex64;
+
+use crate::{
+    blend, cell_random, plot, splat_disk, splat_gaussian, to_image_coord, AntiAlias, Args, Canvas,
+};
+
+/// Steps taken from each grid point before drawing, while the walk is still
+/// converging on the set.
+const TRANSIENT: usize = 16;
+
+/// Walks backwards from each point in the chunk for -l steps, drawing every
+/// step after the transient as a point.
+pub fn iterate_chunk(chunk: &[(f64, f64)], mut canvas: Canvas, args: &Args) -> Canvas {
+    let c = Complex64::new(args.inverse_julia[0], args.inverse_julia[1]);
+    let colour = Luma([args.base_opacity() as u32]);
+    let image = &mut canvas.density;
+
+    for &(re, im) in chunk {
+        let walk = (re.to_bits() ^ im.to_bits().rotate_left(32)) as usize;
+        let mut z = Complex64::new(re, im);
+
+        for step in 0..TRANSIENT + args.limit {
+            z = (z - c).sqrt();
+            if cell_random(args.seed, walk, step, 0) < 0.5 {
+                z = -z;
+            }
+            if step < TRANSIENT {
+                continue;
+            }
+
+            let pos = to_image_coord(z, args);
+            match (args.aa, args.point_size) {
+                (AntiAlias::Gaussian, Some(radius)) => {
+                    splat_disk(image, pos, radius, colour, blend)
+                }
+                (AntiAlias::Gaussian, None) => splat_gaussian(image, pos, colour, blend),
+                _ => plot(image, pos, colour, 1.0, blend),
+            }
+        }
+    }
+
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sample_grid, tests::args};
+
+    #[test]
+    fn draws_the_unit_circle_for_zero() {
+        let args = args(&[
+            "-s",
+            "64",
+            "-z",
+            "16",
+            "-r",
+            "0",
+            "-d",
+            "0.2",
+            "-l",
+            "20",
+            "--inverse-julia",
+            "0,0",
+        ]);
+        // Zero is its own root, so never reaches the set.
+        let grid: Vec<_> = sample_grid(&args)
+            .into_iter()
+            .filter(|&(re, im)| re.hypot(im) > 1e-6)
+            .collect();
+        let canvas = iterate_chunk(&grid, Canvas::new(&args), &args);
+
+        let mut lit = 0;
+        for (x, y, p) in canvas.density.enumerate_pixels() {
+            if p[0] > 0 {
+                lit += 1;
+                let r = (x as f64 + 0.5 - 32.0).hypot(y as f64 + 0.5 - 32.0);
+                assert!(
+                    (r - 16.0).abs() < 1.5,
+                    "lit pixel ({}, {}) off the circle",
+                    x,
+                    y
+                );
+            }
+        }
+        // Most of the circumference is hit.
+        assert!(lit > 80, "{} pixels lit", lit);
+    }
+}
@@ -0,0 +1,133 @@
+//! Layer files for --layers, describing several passes over the same sample
+//! grid that are blended into one image.
+//!
+//! ```toml
+//! # How the layers are combined: "sum" (the default) or "max".
+//! blend = "sum"
+//!
+//! [[layer]]
+//! mode = "escaped"
+//! limit = 500
+//! colour = [255, 140, 40]
+//!
+//! [[layer]]
+//! mode = "trapped"
+//! pow = 2.5
+//! opacity = 16
+//! ```
+//!
+//! Every layer key is optional, and falls back to the value given on the
+//! command line. Layers without a colour are white.
+
+use serde::Deserialize;
+
+use std::fs;
+
+use crate::{Args, DrawMode, PowerBlend};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerFile {
+    pub blend: Option<PowerBlend>,
+    #[serde(rename = "layer", default)]
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    mode: Option<DrawMode>,
+    limit: Option<usize>,
+    pow: Option<f64>,
+    opacity: Option<u16>,
+    pub colour: Option<[u8; 3]>,
+}
+
+impl Layer {
+    /// The command line arguments with this layer's overrides applied.
+    pub fn apply(&self, args: &Args) -> Args {
+        let mut args = args.clone();
+        args.mode = self.mode.unwrap_or(args.mode);
+        args.limit = self.limit.unwrap_or(args.limit);
+        args.pow = self.pow.unwrap_or(args.pow);
+        args.opacity = self.opacity.unwrap_or(args.opacity);
+        args
+    }
+}
+
+pub fn load(path: &str) -> Result<LayerFile, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("Couldn't read layers '{}': {}", path, e))?;
+    let file: LayerFile =
+        toml::from_str(&text).map_err(|e| format!("Invalid layers '{}': {}", path, e))?;
+
+    if file.layers.is_empty() {
+        return Err(format!("Layers '{}' has no [[layer]] tables", path));
+    }
+    for (i, layer) in file.layers.iter().enumerate() {
+        if layer.limit == Some(0) {
+            return Err(format!("Layer {} has a limit of 0", i + 1));
+        }
+        if layer.opacity == Some(0) {
+            return Err(format!(
+                "Layer {} has an opacity of 0, which draws nothing",
+                i + 1
+            ));
+        }
+        if layer.pow.is_some_and(|p| !p.is_finite()) {
+            return Err(format!("Layer {} has a non-finite power", i + 1));
+        }
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::args;
+
+    /// Writes `text` to a file named after the test, and loads it.
+    fn load_text(name: &str, text: &str) -> Result<LayerFile, String> {
+        let path = std::env::temp_dir().join(format!("mandeltrace-{}.toml", name));
+        fs::write(&path, text).unwrap();
+        let file = load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        file
+    }
+
+    #[test]
+    fn layers_override_the_command_line() {
+        let file = load_text(
+            "layers",
+            "blend = \"max\"\n\
+             [[layer]]\nmode = \"escaped\"\nlimit = 500\ncolour = [255, 140, 40]\n\
+             [[layer]]\nmode = \"trapped\"\npow = 2.5\nopacity = 16\n",
+        )
+        .unwrap();
+        assert!(matches!(file.blend, Some(PowerBlend::Max)));
+        assert_eq!(file.layers[0].colour, Some([255, 140, 40]));
+        assert_eq!(file.layers[1].colour, None);
+
+        let base = args(&["-l", "80", "-o", "64"]);
+        let first = file.layers[0].apply(&base);
+        assert!(matches!(first.mode, DrawMode::Escaped));
+        assert_eq!((first.limit, first.pow, first.opacity), (500, 2.0, 64));
+        let second = file.layers[1].apply(&base);
+        assert!(matches!(second.mode, DrawMode::Trapped));
+        assert_eq!((second.limit, second.pow, second.opacity), (80, 2.5, 16));
+    }
+
+    #[test]
+    fn rejects_bad_layers() {
+        let error = |name, text| load_text(name, text).err().unwrap();
+        assert!(error("no-layers", "blend = \"sum\"\n").contains("no [[layer]] tables"));
+        assert_eq!(
+            error("zero-limit", "[[layer]]\n[[layer]]\nlimit = 0\n"),
+            "Layer 2 has a limit of 0"
+        );
+        assert!(error("zero-opacity", "[[layer]]\nopacity = 0\n").contains("draws nothing"));
+        assert!(error("nan-pow", "[[layer]]\npow = nan\n").contains("non-finite power"));
+        assert!(error("unknown-key", "[[layer]]\nzoom = 2\n").starts_with("Invalid layers"));
+    }
+}
@@ -1,16 +1,100 @@
-use image::{LumaA, Pixel, Rgba, RgbaImage};
-use imageproc::drawing::draw_antialiased_line_segment_mut as draw_line;
+use image::{Luma, LumaA, Pixel, Rgba, RgbaImage};
+use imageproc::drawing::{draw_antialiased_line_segment_mut as draw_line, BresenhamLineIter};
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
-use num::complex::Complex64;
+use num::{
+    complex::{Complex, Complex64},
+    Float,
+};
+use palette::Gradient;
+use progress::ProgressFile;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
-use std::{convert::TryInto, str::FromStr};
+use std::{
+    convert::{TryFrom, TryInto},
+    env,
+    error::Error,
+    f64::consts::{FRAC_2_PI, FRAC_PI_2, SQRT_2},
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+mod adaptive;
+mod autofocus;
+mod boundary_seed;
+mod clahe;
+mod compare;
+mod contour;
+mod converge;
+mod deepzoom;
+mod dither;
+mod escape;
+mod estimate;
+mod heatmap;
+mod info;
+mod interactive;
+mod inverse_julia;
+mod layers;
+mod merge;
+mod montage;
+mod orbit_stats;
+mod output;
+mod palette;
+mod progress;
+mod seed;
+mod undersample;
+mod validate;
 
 type Image = image::ImageBuffer<LumaA<u16>, Vec<u16>>;
 
-#[derive(Debug, Copy, Clone)]
+/// Traces as they're drawn: each pixel holds the optical depth of everything
+/// drawn over it, `-ln(1 - alpha)` summed, in fixed point. Integer addition
+/// doesn't depend on the order or grouping of the strokes, so the result is
+/// the same for any chunk length or thread count.
+type Density = image::ImageBuffer<Luma<u32>, Vec<u32>>;
+
+/// Fixed point units per unit of optical depth. A pixel saturates at a depth
+/// of 256, long after it's drawn fully opaque at about 11.8.
+const DEPTH_SCALE: f32 = (1 << 24) as f32;
+
+/// Set by the Ctrl-C handler to stop dispatching new chunks.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum BlendSpace {
+    Linear,
+    Perceptual,
+}
+
+impl FromStr for BlendSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("linear") {
+            Ok(Self::Linear)
+        } else if s.eq_ignore_ascii_case("perceptual") {
+            Ok(Self::Perceptual)
+        } else {
+            Err(format!("Unknown blend space: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 enum DrawMode {
     All,
     Escaped,
@@ -34,7 +118,307 @@ impl FromStr for DrawMode {
     }
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Axis {
+    Re,
+    Im,
+}
+
+impl FromStr for Axis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("re") || s.eq_ignore_ascii_case("real") {
+            Ok(Self::Re)
+        } else if s.eq_ignore_ascii_case("im") || s.eq_ignore_ascii_case("imag") {
+            Ok(Self::Im)
+        } else {
+            Err(format!("Unknown axis: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum AntiAlias {
+    None,
+    Linear,
+    Gaussian,
+}
+
+impl FromStr for AntiAlias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("none") {
+            Ok(Self::None)
+        } else if s.eq_ignore_ascii_case("linear") {
+            Ok(Self::Linear)
+        } else if s.eq_ignore_ascii_case("gaussian") {
+            Ok(Self::Gaussian)
+        } else {
+            Err(format!("Unknown antialiasing mode: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Warp {
+    None,
+    Exp,
+    Log,
+    Mobius,
+}
+
+impl FromStr for Warp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("none") {
+            Ok(Self::None)
+        } else if s.eq_ignore_ascii_case("exp") {
+            Ok(Self::Exp)
+        } else if s.eq_ignore_ascii_case("log") {
+            Ok(Self::Log)
+        } else if s.eq_ignore_ascii_case("mobius") {
+            Ok(Self::Mobius)
+        } else {
+            Err(format!("Unknown warp: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum OrbitTrap {
+    Point,
+    Cross,
+    Circle,
+}
+
+impl FromStr for OrbitTrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("point") {
+            Ok(Self::Point)
+        } else if s.eq_ignore_ascii_case("cross") {
+            Ok(Self::Cross)
+        } else if s.eq_ignore_ascii_case("circle") {
+            Ok(Self::Circle)
+        } else {
+            Err(format!("Unknown orbit trap: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum GridOffset {
+    None,
+    Half,
+    Random,
+}
+
+impl FromStr for GridOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("none") {
+            Ok(Self::None)
+        } else if s.eq_ignore_ascii_case("half") {
+            Ok(Self::Half)
+        } else if s.eq_ignore_ascii_case("random") {
+            Ok(Self::Random)
+        } else {
+            Err(format!("Unknown grid offset: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum AlphaCurve {
+    Linear,
+    Sigmoid,
+    Log,
+}
+
+impl FromStr for AlphaCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("linear") {
+            Ok(Self::Linear)
+        } else if s.eq_ignore_ascii_case("sigmoid") {
+            Ok(Self::Sigmoid)
+        } else if s.eq_ignore_ascii_case("log") {
+            Ok(Self::Log)
+        } else {
+            Err(format!("Unknown alpha curve: '{}'", s))
+        }
+    }
+}
+
+impl AlphaCurve {
+    /// Maps a density in `[0, 1]` to an alpha in `[0, 1]`.
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            AlphaCurve::Linear => t,
+            // A logistic curve rescaled so 0 and 1 map to themselves.
+            AlphaCurve::Sigmoid => {
+                let logistic = |t: f64| 1.0 / (1.0 + (-10.0 * (t - 0.5)).exp());
+                (logistic(t) - logistic(0.0)) / (logistic(1.0) - logistic(0.0))
+            }
+            AlphaCurve::Log => (1.0 + 100.0 * t).ln() / 101_f64.ln(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum PowerBlend {
+    Sum,
+    Max,
+}
+
+impl FromStr for PowerBlend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("sum") {
+            Ok(Self::Sum)
+        } else if s.eq_ignore_ascii_case("max") {
+            Ok(Self::Max)
+        } else {
+            Err(format!("Unknown power blend: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Projection {
+    Planar,
+    Stereographic,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("planar") {
+            Ok(Self::Planar)
+        } else if s.eq_ignore_ascii_case("stereographic") {
+            Ok(Self::Stereographic)
+        } else {
+            Err(format!("Unknown projection: '{}'", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Branch {
+    Principal,
+    Alternate,
+    Random,
+}
+
+impl FromStr for Branch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("principal") {
+            Ok(Self::Principal)
+        } else if s.eq_ignore_ascii_case("alternate") {
+            Ok(Self::Alternate)
+        } else if s.eq_ignore_ascii_case("random") {
+            Ok(Self::Random)
+        } else {
+            Err(format!("Unknown branch: '{}'", s))
+        }
+    }
+}
+
+/// The two ends of a colour ramp, such as --depth-colours or --bg-gradient,
+/// from 0 to 1.
+#[derive(Debug, Copy, Clone, Serialize)]
+struct ColourRamp([[f32; 3]; 2]);
+
+impl ColourRamp {
+    /// The colour at `t` along the ramp, from 0 to 1.
+    fn lerp(&self, t: f32) -> [f32; 3] {
+        let [c0, c1] = self.0;
+        [0, 1, 2].map(|c| c0[c] + (c1[c] - c0[c]) * t)
+    }
+
+    /// The colour of step `i` of an orbit with `steps` steps.
+    fn at(&self, i: usize, steps: usize) -> [f32; 3] {
+        let t = if steps > 1 {
+            i as f32 / (steps - 1) as f32
+        } else {
+            0.0
+        };
+        self.lerp(t)
+    }
+}
+
+impl FromStr for ColourRamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Expected two #rrggbb colours separated by a comma: '{}'", s);
+        let (c0, c1) = s.split_once(',').ok_or_else(err)?;
+        let parse = |c: &str| {
+            palette::parse_hex(c.trim())
+                .map(|rgb| rgb.map(|v| (v / 255.0) as f32))
+                .ok_or_else(err)
+        };
+
+        Ok(Self([parse(c0)?, parse(c1)?]))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+struct Crop {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid crop geometry, expected WxH+X+Y: '{}'", s);
+
+        let (size, offset) = s.trim().split_once('+').ok_or_else(err)?;
+        let (width, height) = size.split_once('x').ok_or_else(err)?;
+        let (x, y) = offset.split_once('+').ok_or_else(err)?;
+
+        Ok(Self {
+            width: width.parse().map_err(|_| err())?,
+            height: height.parse().map_err(|_| err())?,
+            x: x.parse().map_err(|_| err())?,
+            y: y.parse().map_err(|_| err())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, StructOpt, Serialize)]
 struct Args {
     #[structopt(short = "s", default_value = "2000")]
     size: u32,
@@ -45,6 +429,14 @@ struct Args {
     #[structopt(short = "d", default_value = "0.01")]
     delta: f64,
 
+    /// Grid spacing along the real axis, instead of -d.
+    #[structopt(long = "delta-re")]
+    delta_re: Option<f64>,
+
+    /// Grid spacing along the imaginary axis, instead of -d.
+    #[structopt(long = "delta-im")]
+    delta_im: Option<f64>,
+
     #[structopt(short = "l", default_value = "100")]
     limit: usize,
 
@@ -60,6 +452,12 @@ struct Args {
     #[structopt(long = "chunk_len", default_value = "50000")]
     chunk_len: usize,
 
+    /// Split the grid into much smaller chunks than --chunk_len, and draw
+    /// each thread's chunks onto one shared canvas, so idle threads can keep
+    /// stealing work until the end of the render.
+    #[structopt(long = "chunk-balance")]
+    chunk_balance: bool,
+
     #[structopt(short = "o", default_value = "64")]
     opacity: u16,
 
@@ -69,171 +467,3227 @@ struct Args {
     #[structopt(long = "mb")]
     overlay_mandel: bool,
 
+    /// Iteration limit for the --mb overlay, instead of -l. The overlay
+    /// iterates every pixel, so a lower limit keeps it cheap next to a high
+    /// trace limit.
+    #[structopt(long = "overlay-limit", requires = "overlay-mandel")]
+    overlay_limit: Option<usize>,
+
+    /// Instead of the density, draw this many contour lines through it,
+    /// evenly spaced between black and the brightest pixel, for a
+    /// topographic look.
+    #[structopt(long = "contour")]
+    contour: Option<u32>,
+
+    /// The colour of --contour lines, as `#rrggbb`. Defaults to white.
+    #[structopt(long = "contour-colour", alias = "contour-color", requires = "contour")]
+    contour_colour: Option<contour::LineColour>,
+
+    /// Draw the traces over a gradient between two colours instead of black,
+    /// given as `#rrggbb,#rrggbb`.
+    #[structopt(
+        long = "bg-gradient",
+        conflicts_with_all = &["overlay-mandel", "transparent-bg"]
+    )]
+    bg_gradient: Option<ColourRamp>,
+
+    /// Make --bg-gradient radial, from the first colour in the centre to the
+    /// second in the corners.
+    #[structopt(long = "bg-gradient-radial", requires = "bg-gradient")]
+    bg_gradient_radial: bool,
+
+    /// The direction of a linear --bg-gradient in degrees, clockwise from
+    /// left to right. Defaults to 90, top to bottom.
+    #[structopt(
+        long = "bg-gradient-angle",
+        requires = "bg-gradient",
+        conflicts_with = "bg-gradient-radial"
+    )]
+    bg_gradient_angle: Option<f64>,
+
     #[structopt(default_value = "image.png")]
     image_name: String,
 
     #[structopt(short = "p", default_value = "2.0")]
     pow: f64,
-}
 
-struct ArrWindows<'a, T, const N: usize>(&'a [T]);
-impl<'a, T, const N: usize> Iterator for ArrWindows<'a, T, N> {
-    type Item = &'a [T; N];
+    /// Crop the output image, given as WxH+X+Y.
+    #[structopt(long = "crop")]
+    crop: Option<Crop>,
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let next = self.0.get(..N)?;
-        self.0 = self.0.get(1..)?;
-        next.try_into().ok()
-    }
-}
+    /// Only trace every Nth grid point, scaling opacity by N to compensate.
+    #[structopt(long = "sample-stride", default_value = "1")]
+    sample_stride: usize,
 
-fn mandelbrot(z: Complex64, (x, y): (f64, f64), args: &Args) -> Complex64 {
-    z.powf(args.pow) + Complex64::new(x, y)
-}
+    /// Write a CSV histogram of orbit points projected onto an axis instead
+    /// of rendering an image.
+    #[structopt(long = "axis-hist-out", conflicts_with = "projection")]
+    axis_hist_out: Option<String>,
 
-fn to_image_coord(z: Complex64, args: &Args) -> (i32, i32) {
-    let pos_x = (args.size as f64 / 2.0) + (z.re + args.re_off) * args.zoom;
-    let pos_y = (args.size as f64 / 2.0) + (z.im + args.im_off) * args.zoom;
-    (pos_x as i32, pos_y as i32)
-}
+    /// Write a CSV with one row of statistics for each orbit that would be
+    /// drawn, instead of rendering an image. See the orbit_stats module for
+    /// the columns.
+    #[structopt(
+        long = "orbit-stats",
+        alias = "save-trajectory-stats",
+        conflicts_with_all = &[
+            "axis-hist-out",
+            "count-only",
+            "estimate",
+            "interactive",
+            "vary",
+            "inverse-julia"
+        ]
+    )]
+    orbit_stats: Option<String>,
 
-fn to_complex_coord(x: u32, y: u32, args: &Args) -> Complex64 {
-    let pos_x = (x as f64 - args.size as f64 / 2.0) / args.zoom - args.re_off;
-    let pos_y = (y as f64 - args.size as f64 / 2.0) / args.zoom - args.im_off;
+    /// Keep a JSON progress status in this file, updated after each chunk,
+    /// for monitoring from another process.
+    #[structopt(long = "progress-file")]
+    progress_file: Option<String>,
 
-    Complex64::new(pos_x, pos_y)
-}
+    /// Estimate the time left from a moving average of how long roughly the
+    /// last N chunks took, instead of from the average over the whole
+    /// render, so the ETA follows changes in chunk cost without jumping.
+    #[structopt(long = "eta-window")]
+    eta_window: Option<usize>,
 
-fn blend(mut a: LumaA<u16>, mut b: LumaA<u16>, alpha: f32) -> LumaA<u16> {
-    a.0[1] = (a.0[1] as f32 * alpha) as u16;
-    b.blend(&a);
-    b
-}
+    /// The axis to project onto for --axis-hist-out, either re or im.
+    #[structopt(long = "axis", default_value = "re")]
+    axis: Axis,
 
-fn iterate_coordinate(coord: (f64, f64), args: &Args) -> Option<Vec<Complex64>> {
-    let mut z = mandelbrot(Complex64::default(), coord, args);
-    let mut points = Vec::with_capacity(args.limit + 1);
-    points.push(z);
+    /// Only draw orbit segments whose endpoints are inside the (power 2) set.
+    /// This runs a full escape test for every orbit point, so it costs up to
+    /// `limit` extra iterations per point.
+    #[structopt(long = "clip-to-set")]
+    clip_to_set: bool,
 
-    let mut did_escape = false;
-    for _ in 0..args.limit {
-        z = mandelbrot(z, coord, args);
-        points.push(z);
+    /// Only draw orbit points inside this rectangle of the complex plane,
+    /// given as re0,im0,re1,im1, whatever the view. Orbits are still
+    /// iterated in full, but segments with an end outside aren't drawn.
+    #[structopt(
+        long = "draw-region",
+        use_delimiter = true,
+        allow_hyphen_values = true,
+        number_of_values = 4
+    )]
+    draw_region: Vec<f64>,
 
-        if z.im.abs() > args.bounds || z.re.abs() > args.bounds {
-            did_escape = true;
-            break;
-        }
-    }
+    /// Alpha composite these comma separated images in order, instead of
+    /// rendering.
+    #[structopt(long = "composite", use_delimiter = true, requires = "composite-out")]
+    composite: Vec<String>,
 
-    use DrawMode::*;
-    match (args.mode, did_escape) {
-        (All, _) => Some(points),
-        (Escaped, true) => Some(points),
-        (Trapped, false) => Some(points),
-        _ => None,
-    }
-}
+    #[structopt(long = "composite-out", requires = "composite")]
+    composite_out: Option<String>,
 
-fn iterate_chunk(chunk: &[(&f64, &f64)], mut image: Image, args: &Args) -> Image {
-    let traces = chunk
-        .iter()
-        .filter_map(|&(&x, &y)| iterate_coordinate((x, y), args));
-
-    for t in traces {
-        for &[w1, w2] in ArrWindows(&t) {
-            draw_line(
-                &mut image,
-                to_image_coord(w1, args),
-                to_image_coord(w2, args),
-                LumaA([u16::max_value(), args.opacity]),
-                blend,
-            );
-        }
-    }
+    /// Sum renders of the same view into the image name instead of
+    /// rendering, each scaled by a weight given as `path*weight`, for example
+    /// `a.png*0.5,b.png*2`. A path without a weight counts once. Writes 16
+    /// bits per channel where the format allows.
+    #[structopt(
+        long = "merge-weighted",
+        require_delimiter = true,
+        conflicts_with = "composite"
+    )]
+    merge_weighted: Vec<String>,
 
-    image
-}
+    /// Render a montage of every combination of the varied arguments, given
+    /// as `key=v1,v2,...` and repeatable. The first --vary runs along the
+    /// columns and the rest down the rows. Each cell is a full render at
+    /// --size. Keys are bounds, delta, limit, zoom, re_off, im_off, opacity,
+    /// pow and seed.
+    #[structopt(
+        long = "vary",
+        number_of_values = 1,
+        conflicts_with_all = &[
+            "interactive",
+            "autofocus",
+            "count-only",
+            "estimate",
+            "axis-hist-out",
+            "compare",
+            "record-params"
+        ]
+    )]
+    vary: Vec<montage::Vary>,
 
-fn to_u8_image(image: &Image, base: Option<RgbaImage>) -> RgbaImage {
-    let mut out = base.unwrap_or_else(|| {
-        RgbaImage::from_pixel(image.width(), image.height(), Rgba([0, 0, 0, 255]))
-    });
+    /// Render the view at each of these bounds, given as b1,b2,..., and tile
+    /// them left to right into a strip, as `--vary bounds=b1,b2,...` would.
+    /// With --vary too, the sweep runs along the columns. -b is also the
+    /// extent of the grid, so larger bounds sample more points as well as
+    /// keeping more of each orbit's escaping tail.
+    #[structopt(
+        long = "bounds-sweep",
+        require_delimiter = true,
+        conflicts_with_all = &[
+            "interactive",
+            "autofocus",
+            "count-only",
+            "estimate",
+            "axis-hist-out",
+            "compare",
+            "record-params"
+        ]
+    )]
+    bounds_sweep: Vec<f64>,
 
-    out.pixels_mut().zip(image.pixels()).for_each(|(o, i)| {
-        let i = Rgba([255, 255, 255, (i[0] >> 8) as u8]);
-        o.blend(&i);
-    });
+    /// Print the parameters recorded beside an image by --record-params, and
+    /// the command line that reproduces it, instead of rendering.
+    #[structopt(long = "info", conflicts_with_all = &["composite", "merge-weighted"])]
+    info: Option<String>,
 
-    out
-}
+    /// Line antialiasing: none (aliased, fastest), linear, or gaussian (splats
+    /// each orbit point for a softer glow).
+    #[structopt(long = "aa", default_value = "linear")]
+    aa: AntiAlias,
 
-fn main() {
-    let args = Args::from_args();
+    /// With --aa gaussian, draw each orbit point as an antialiased disk of
+    /// this radius in pixels instead of the gaussian splat. 0 plots a single
+    /// pixel.
+    #[structopt(long = "point-size")]
+    point_size: Option<f32>,
 
-    let canvas = Image::from_pixel(args.size, args.size, LumaA([0, 0]));
+    /// Draw each trace into a scratch buffer that keeps the brightest
+    /// coverage of each pixel, then add it to the canvas, so an orbit that
+    /// crosses itself only counts once per pixel. Costs an extra size by
+    /// size buffer per chunk, and a second pass over each trace's pixels.
+    #[structopt(long = "per-trace-max")]
+    per_trace_max: bool,
 
-    let coords: Vec<_> = (0_u32..)
-        .map(|x| -args.bounds + x as f64 * args.delta)
-        .take_while(|&x| x < args.bounds)
-        .collect();
-    let all_coords: Vec<_> = coords.iter().cartesian_product(coords.iter()).collect();
+    /// Draw lines from their exact endpoints rather than the pixels they
+    /// fall in, spreading each step along the line over the four nearest
+    /// pixels, so every segment deposits its length in pixels.
+    #[structopt(long = "subpixel")]
+    subpixel: bool,
 
-    let bar = ProgressBar::new((all_coords.len() / args.chunk_len) as u64);
-    bar.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}/{eta_precise}] {wide_bar:.white} {pos:>7}/{len:7} {msg}")
-            .progress_chars("█▓▒░  "),
-    );
+    /// Colour the overlay by each orbit's Lyapunov exponent. Only valid for
+    /// power 2.
+    #[structopt(long = "lyapunov", requires = "overlay-mandel")]
+    lyapunov: bool,
 
-    let canvas = all_coords
-        .par_chunks(args.chunk_len)
-        .map(|c| {
-            let chunk = iterate_chunk(c, canvas.clone(), &args);
-            bar.inc(1);
-            chunk
-        })
-        .reduce(
-            || canvas.clone(),
-            |mut blend, chunk| {
-                blend
-                    .pixels_mut()
-                    .zip(chunk.pixels())
-                    .for_each(|(o, i)| o.blend(i));
-
-                blend
-            },
-        );
+    /// Skip drawing the first K points of each orbit, leaving only the part
+    /// that has settled onto its attractor.
+    #[structopt(long = "trim-transient", default_value = "0")]
+    trim_transient: usize,
 
-    let mut background = Image::from_pixel(args.size, args.size, LumaA([0, u16::MAX]));
-    background
-        .pixels_mut()
-        .zip(canvas.pixels())
-        .for_each(|(o, i)| o.blend(i));
+    /// Physical resolution to store in the output file. Only PNG and TIFF
+    /// outputs support this.
+    #[structopt(long = "dpi")]
+    dpi: Option<u32>,
 
-    let mandel = args.overlay_mandel.then(|| {
-        RgbaImage::from_fn(args.size, args.size, |x, y| {
-            let cmpl = to_complex_coord(x, y, &args);
+    /// On Ctrl-C, stop starting new chunks and save what has been rendered so
+    /// far to `<name>.partial.<ext>`.
+    #[structopt(long = "partial-save")]
+    partial_save: bool,
 
-            let mut z = Complex64::default();
-            let mut did_escape = false;
-            for _ in 0..args.limit {
-                z = mandelbrot(z, (cmpl.re, cmpl.im), &args);
+    /// Once this many seconds have passed since starting, stop starting new
+    /// chunks and save what has been rendered to `<name>.partial.<ext>`, as
+    /// with --partial-save. Chunks already started are finished first.
+    #[structopt(long = "max-time")]
+    max_time: Option<f64>,
 
-                if z.norm_sqr() > 4.0 {
-                    did_escape = true;
-                    break;
-                }
-            }
+    /// Instead of a single pass over the grid, keep rendering it in batches,
+    /// each sample jittered within its cell, and average them until a batch
+    /// changes the average brightness by no more than this much per pixel,
+    /// from 0 to 1. Stops after 64 batches at most.
+    #[structopt(
+        long = "target-quality",
+        alias = "sample-count-target",
+        conflicts_with_all = &["grid-offset", "progress-file", "interactive"]
+    )]
+    target_quality: Option<f64>,
 
-            if did_escape {
-                Rgba([128, 0, 0, 255])
-            } else {
-                Rgba([0, 0, 0, 255])
-            }
-        })
-    });
+    /// After rendering the grid, draw this many extra samples, picked in
+    /// proportion to how detailed the first pass is where their orbits land.
+    /// See the adaptive module for the heuristic and its bias.
+    #[structopt(
+        long = "adaptive-samples",
+        conflicts_with_all = &["target-quality", "progress-file", "interactive", "inverse-julia"]
+    )]
+    adaptive_samples: Option<usize>,
+
+    /// Locally equalise brightness over tiles of this many pixels, bringing
+    /// out faint detail next to bright regions.
+    #[structopt(long = "adaptive-normalize")]
+    adaptive_normalize: Option<u32>,
+
+    /// Dithering used when reducing to 8 bits: none, ordered, or
+    /// error-diffusion.
+    #[structopt(long = "dither", default_value = "none")]
+    dither: dither::Dither,
+
+    /// Reflect the output into a 2x2 symmetric tiling. Same as passing both
+    /// --mirror-x and --mirror-y.
+    #[structopt(long = "mirror-output")]
+    mirror_output: bool,
+
+    /// Draw every orbit this many times, rotated in equal steps about the
+    /// centre of the view, for K-fold mandala-like symmetry. This is purely
+    /// decoration: the copies aren't orbits of anything. Each copy adds to
+    /// the drawing cost and the brightness, so lower -o to compensate.
+    #[structopt(long = "symmetrize", default_value = "1")]
+    symmetrize: u32,
+
+    /// Append a horizontally flipped copy to the right of the output.
+    #[structopt(long = "mirror-x")]
+    mirror_x: bool,
+
+    /// Append a vertically flipped copy below the output.
+    #[structopt(long = "mirror-y")]
+    mirror_y: bool,
+
+    /// Divide each orbit's opacity by the iteration it escaped at, so every
+    /// orbit contributes roughly the same total brightness however long it
+    /// survived. Trapped orbits count as escaping at the limit, and opacity
+    /// never drops below 1, so raise -o to keep precision.
+    #[structopt(long = "escape-weight")]
+    escape_weight: bool,
+
+    /// Scale each orbit's opacity by `escaped_at / escape limit`, so orbits
+    /// escaping just before the limit, from near the boundary, are drawn at
+    /// full opacity and quick escapes are faint. The opposite of
+    /// --escape-weight. Trapped orbits get full opacity, and opacity never
+    /// drops below 1.
+    #[structopt(long = "limit-proximity-weight", conflicts_with = "escape-weight")]
+    limit_proximity_weight: bool,
+
+    /// Conformal map applied to each sampling coordinate before iterating:
+    /// none, exp, log, or mobius.
+    #[structopt(long = "warp", default_value = "none")]
+    warp: Warp,
+
+    /// Coefficients a,b,c,d of the mobius warp (az+b)/(cz+d). Either four
+    /// real values, or eight values as re,im pairs.
+    #[structopt(long = "warp-params", use_delimiter = true, allow_hyphen_values = true)]
+    warp_params: Vec<f64>,
+
+    /// Print how many orbits would be drawn, without rendering.
+    #[structopt(long = "count-only")]
+    count_only: bool,
+
+    /// Orbits must escape within this many iterations to count as escaped.
+    /// Defaults to -l.
+    #[structopt(long = "escape-limit")]
+    escape_limit: Option<usize>,
+
+    /// Orbits must survive this many iterations to count as trapped. Orbits
+    /// escaping between the two limits are not drawn. Defaults to -l.
+    #[structopt(long = "trap-limit")]
+    trap_limit: Option<usize>,
+
+    /// Leave the background transparent, so trace density is carried only by
+    /// alpha. Ignored with --mb, which provides its own opaque background.
+    #[structopt(long = "transparent-bg")]
+    transparent_bg: bool,
+
+    /// How trace density maps to the alpha the traces are drawn with:
+    /// linear, sigmoid (more contrast), or log (lifts faint traces). With
+    /// --transparent-bg this shapes the output alpha alone.
+    #[structopt(long = "alpha-curve", default_value = "linear")]
+    alpha_curve: AlphaCurve,
+
+    /// Treat the normalized density as linear light and encode it with the
+    /// sRGB transfer curve when converting to 8-bit, instead of writing it
+    /// out as is. This brightens everything but the extremes.
+    #[structopt(long = "output-srgb")]
+    output_srgb: bool,
+
+    /// Where overlapping segments are composited: linear (physically
+    /// correct), or perceptual, in sRGB-encoded values. Perceptual gives
+    /// faint traces less light, so a lone trace is dimmer, and overlaps gain
+    /// more brightness each before saturating.
+    #[structopt(long = "blend-space", default_value = "linear")]
+    blend_space: BlendSpace,
+
+    /// Set the zoom as a power of ten, so `--zoom-log 3` is `-z 1000`.
+    #[structopt(long = "zoom-log", conflicts_with = "zoom", allow_hyphen_values = true)]
+    zoom_log: Option<f64>,
+
+    /// Write the resolved arguments and render metadata to a JSON file next
+    /// to the output image.
+    #[structopt(long = "record-params")]
+    record_params: bool,
+
+    /// Clamp problematic arguments to sensible values instead of exiting.
+    #[structopt(long = "auto-fix")]
+    auto_fix: bool,
+
+    /// White balance of the output in kelvin. Lower is warmer, higher is
+    /// cooler, and 6500 leaves the image unchanged.
+    #[structopt(long = "temperature", default_value = "6500")]
+    temperature: f64,
+
+    /// Colour the overlay by how close each orbit comes to a trap shape:
+    /// point, cross (the axes through --trap-pos), or circle.
+    #[structopt(
+        long = "orbit-trap",
+        requires = "overlay-mandel",
+        conflicts_with = "lyapunov"
+    )]
+    orbit_trap: Option<OrbitTrap>,
+
+    /// Centre of the orbit trap, as re,im.
+    #[structopt(
+        long = "trap-pos",
+        use_delimiter = true,
+        allow_hyphen_values = true,
+        number_of_values = 2,
+        default_value = "0,0"
+    )]
+    trap_pos: Vec<f64>,
+
+    /// Draw the Julia set of this c, given as re,im, by inverse iteration
+    /// from each grid point instead of tracing Mandelbrot orbits. Each grid
+    /// point takes -l steps backwards, choosing between the two square roots
+    /// at random, and --mode is ignored. Power 2 only.
+    #[structopt(
+        long = "inverse-julia",
+        alias = "iterate-backward",
+        use_delimiter = true,
+        allow_hyphen_values = true,
+        number_of_values = 2,
+        conflicts_with_all = &[
+            "powers",
+            "layers",
+            "double",
+            "rgb-phase",
+            "trajectory-direction",
+            "trap-attractor-cluster",
+            "depth-colours",
+            "interactive",
+            "count-only",
+            "estimate",
+            "axis-hist-out"
+        ]
+    )]
+    inverse_julia: Vec<f64>,
+
+    /// Radius of the circle orbit trap.
+    #[structopt(long = "trap-radius", default_value = "0.5")]
+    trap_radius: f64,
+
+    /// Fill the overlay with a classic escape time render, shading each
+    /// pixel by how many iterations its own orbit takes to escape.
+    #[structopt(
+        long = "escape-map",
+        requires = "overlay-mandel",
+        conflicts_with_all = &["lyapunov", "orbit-trap"]
+    )]
+    escape_map: bool,
+
+    /// Shade the --mb exterior by its distance estimate instead of a flat
+    /// fill, brightest at the boundary and fading to black away from it. Only
+    /// valid for power 2.
+    #[structopt(
+        long = "mandelbrot-distance-shading",
+        requires = "overlay-mandel",
+        conflicts_with_all = &["lyapunov", "orbit-trap", "escape-map"]
+    )]
+    mandelbrot_distance_shading: bool,
+
+    /// How far from the boundary, in pixels, --mandelbrot-distance-shading
+    /// fades over. Defaults to 8.
+    #[structopt(long = "overlay-falloff", requires = "mandelbrot-distance-shading")]
+    overlay_falloff: Option<f64>,
+
+    /// Offset grid points to break up lattice artifacts: none, half (shift
+    /// the whole grid by half a delta), or random (jitter each cell within
+    /// its delta, using --seed).
+    #[structopt(long = "grid-offset", default_value = "none")]
+    grid_offset: GridOffset,
+
+    /// Seed for anything pseudo-random, so renders are reproducible.
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Gamma applied to the normalized trace density, before it's coloured.
+    /// This shapes how hit counts map to brightness.
+    #[structopt(long = "density-gamma", default_value = "1.0")]
+    density_gamma: f64,
+
+    /// Gamma applied to the final colours, after the overlay and tint. This
+    /// corrects for the display rather than reshaping the density.
+    #[structopt(long = "display-gamma", default_value = "1.0")]
+    display_gamma: f64,
+
+    /// Render a coarse pass first and move the centre (-r, -i) to the area with the most
+    /// structure, found by the strongest density gradient
+    #[structopt(long = "autofocus")]
+    autofocus: bool,
+
+    /// Render at N times the size and zoom, then downscale with a Lanczos
+    /// filter for whole image anti-aliasing. Costs N^2 the memory and pixel
+    /// work, and opacity is scaled by N to keep the same brightness.
+    #[structopt(long = "render-scale", default_value = "1")]
+    render_scale: u32,
+
+    /// Render once per power in this list and blend the results, instead of
+    /// once at -p. Each power costs a full render. The overlay still uses -p.
+    #[structopt(
+        long = "powers",
+        use_delimiter = true,
+        allow_hyphen_values = true,
+        conflicts_with_all = &["count-only", "axis-hist-out"]
+    )]
+    powers: Vec<f64>,
+
+    /// With --powers, also save the blend of the passes so far after each
+    /// power is added, to `<prefix>_<power>.png`, such as `step_2.0.png` and
+    /// `step_2.5.png` for `--save-incremental step`. The last is the same as
+    /// the final image.
+    #[structopt(long = "save-incremental", requires = "powers")]
+    save_incremental: Option<String>,
+
+    /// How --powers layers are combined: sum or max.
+    #[structopt(long = "power-blend", default_value = "sum")]
+    power_blend: PowerBlend,
+
+    /// Tint each --powers layer along the fire gradient, so each pixel takes
+    /// the colour of the powers that drew it.
+    #[structopt(long = "power-tint", requires = "powers")]
+    power_tint: bool,
+
+    /// Iterate orbits in single precision with a quarter of the limit, for a
+    /// quick approximate look. f32 loses detail from zooms of a few thousand,
+    /// and the overlay still uses full precision.
+    #[structopt(long = "fast-preview")]
+    fast_preview: bool,
+
+    /// Render each layer of a TOML layer file and blend them into one image.
+    /// See the layers module for the format.
+    #[structopt(
+        long = "layers",
+        conflicts_with_all = &["powers", "count-only", "axis-hist-out"]
+    )]
+    layers: Option<String>,
+
+    /// Print a predicted render time, from timing a random sample of the
+    /// grid, instead of rendering.
+    #[structopt(long = "estimate", conflicts_with_all = &["count-only", "axis-hist-out"])]
+    estimate: bool,
+
+    /// Only trace grid points within this distance of the set's boundary,
+    /// using the distance estimate. Points that never escape are kept. Only
+    /// valid for power 2.
+    #[structopt(long = "boundary-only")]
+    boundary_only: Option<f64>,
+
+    /// Colour the traces by the phase (complex argument) of the orbit point
+    /// each segment starts at, around the hue wheel. Each pixel takes the
+    /// average of the hues drawn through it, weighted by their opacity, and
+    /// its brightness still comes from the density.
+    #[structopt(long = "rgb-phase", conflicts_with_all = &["powers", "layers"])]
+    rgb_phase: bool,
+
+    /// Colour each segment red if the orbit steps away from the origin and
+    /// blue if it steps towards it. Uses the same weighted RGB accumulation
+    /// as --rgb-phase.
+    #[structopt(
+        long = "trajectory-direction",
+        conflicts_with_all = &["rgb-phase", "powers", "layers"]
+    )]
+    trajectory_direction: bool,
+
+    /// Colour each trapped orbit by the period of the cycle it settles into,
+    /// giving each period its own hue. An orbit's period is the smallest p
+    /// up to 64 for which its last point is within 1e-6 of the point p steps
+    /// earlier. Escaped orbits, and trapped ones that haven't settled within
+    /// the tolerance, are white. Uses the same weighted RGB accumulation as
+    /// --rgb-phase.
+    #[structopt(
+        long = "trap-attractor-cluster",
+        conflicts_with_all = &["rgb-phase", "trajectory-direction", "powers", "layers"]
+    )]
+    trap_attractor_cluster: bool,
+
+    /// Colour each orbit along its length, from the first colour at its
+    /// first step to the second at its last, given as `#rrggbb,#rrggbb`.
+    /// Uses the same weighted RGB accumulation as --rgb-phase.
+    #[structopt(
+        long = "depth-colours",
+        alias = "depth-colors",
+        conflicts_with_all = &[
+            "rgb-phase",
+            "trajectory-direction",
+            "trap-attractor-cluster",
+            "powers",
+            "layers"
+        ]
+    )]
+    depth_colours: Option<ColourRamp>,
+
+    /// After rendering, report how many lit pixels have only one or two hits,
+    /// and how many of those are lone specks, and warn if -d looks too coarse
+    /// for the zoom.
+    #[structopt(long = "undersample-check")]
+    undersample_check: bool,
+
+    /// Clear pixels drawn by fewer than this many segments before tone
+    /// mapping, to remove the speckle of sparsely sampled areas. Hits are
+    /// counted in segments at the base opacity, and antialiased edges count
+    /// fractionally. 0 keeps everything.
+    #[structopt(long = "density-floor", default_value = "0")]
+    density_floor: f64,
 
-    let canvas = to_u8_image(&background, mandel);
-    canvas.save(&args.image_name).unwrap();
+    /// How the plane is mapped to the image: planar, or stereographic, which
+    /// puts the plane on the Riemann sphere and fits the whole of it in a
+    /// disk, at the same scale as planar in the centre.
+    #[structopt(long = "projection", default_value = "planar")]
+    projection: Projection,
+
+    /// Visit grid points in Z-order instead of column by column, so each
+    /// chunk covers a compact patch of the grid.
+    #[structopt(long = "spatial-order")]
+    spatial_order: bool,
+
+    /// Skip orbits with fewer than this many points. The default of 2 keeps
+    /// every orbit with at least one segment.
+    #[structopt(long = "min-orbit-length", default_value = "2")]
+    min_orbit_length: usize,
+
+    /// After a preview render, read commands from stdin to move around and
+    /// re-render without regenerating the grid. Type `help` for the list.
+    #[structopt(
+        long = "interactive",
+        conflicts_with_all = &["count-only", "estimate", "axis-hist-out"]
+    )]
+    interactive: bool,
+
+    /// Scale opacity so brightness stays about the same when --size, -z or
+    /// -d change, matching the defaults at -o.
+    #[structopt(long = "auto-exposure")]
+    auto_exposure: bool,
+
+    /// The opacity factor from --auto-exposure, or 1.
+    #[structopt(skip = 1.0)]
+    #[serde(skip)]
+    exposure: f64,
+
+    /// Colour the density with a gradient from this file instead of white:
+    /// `position #rrggbb` stops, one per line, or a GIMP .ggr gradient. Over
+    /// --transparent-bg or an overlay, the density still sets the opacity.
+    #[structopt(
+        long = "palette-file",
+        conflicts_with_all = &[
+            "rgb-phase",
+            "trajectory-direction",
+            "trap-attractor-cluster",
+            "depth-colours",
+            "power-tint"
+        ]
+    )]
+    palette_file: Option<String>,
+
+    /// Colour each pixel by its log density instead, along --palette-file
+    /// or a blue to red gradient, from the sparsest lit pixel to the
+    /// densest, with everything lit drawn opaque. See the heatmap module for
+    /// how this differs from a palette alone. --alpha-curve, --output-srgb
+    /// and --dither don't apply.
+    #[structopt(
+        long = "heatmap",
+        conflicts_with_all = &[
+            "rgb-phase",
+            "trajectory-direction",
+            "trap-attractor-cluster",
+            "depth-colours",
+            "power-tint",
+            "contour"
+        ]
+    )]
+    heatmap: bool,
+
+    /// With a colouring (--rgb-phase, --trajectory-direction,
+    /// --trap-attractor-cluster, --depth-colours, --power-tint or coloured
+    /// --layers), stretch each of red, green and blue so its
+    /// brightest pixel is full brightness, instead of keeping the channels
+    /// relative to each other.
+    #[structopt(long = "normalize-per-channel")]
+    normalize_per_channel: bool,
+
+    /// Draw sample points in proportion to the brightness of this image,
+    /// laid over the view, instead of on a uniform grid. Feeding a render
+    /// back in refines where it's already bright; see the seed module for
+    /// the trade-offs.
+    #[structopt(
+        long = "seed-from",
+        conflicts_with_all = &["grid-offset", "sample-stride", "view-sampling", "spatial-order"]
+    )]
+    seed_from: Option<String>,
+
+    /// How many points --seed-from draws. Defaults to the number of points
+    /// the uniform grid would have.
+    #[structopt(long = "seed-samples", requires = "seed-from")]
+    seed_samples: Option<usize>,
+
+    /// Sample this many points from the cells of a coarse escape grid that
+    /// the set's boundary runs through, instead of from the uniform grid,
+    /// so fewer samples are spent deep inside the set or far outside it.
+    /// Uses --seed.
+    #[structopt(
+        long = "boundary-seed",
+        conflicts_with_all = &[
+            "seed-from",
+            "grid-offset",
+            "sample-stride",
+            "view-sampling",
+            "spatial-order"
+        ]
+    )]
+    boundary_seed: Option<usize>,
+
+    /// How many cells per side the escape grid for --boundary-seed has, over
+    /// the whole of -b. Finer grids keep the samples closer to the boundary,
+    /// but take longer to classify.
+    #[structopt(long = "boundary-resolution", default_value = "256")]
+    boundary_resolution: u32,
+
+    /// The map loaded from --seed-from.
+    #[structopt(skip)]
+    #[serde(skip)]
+    seed_map: Option<Arc<seed::SeedMap>>,
+
+    /// The gradient loaded from --palette-file.
+    #[structopt(skip)]
+    #[serde(skip)]
+    palette: Option<Gradient>,
+
+    /// Also write each pass's density as a 16-bit greyscale PNG, for grading
+    /// elsewhere. Three passes (for example three --layers with different
+    /// limits) are named BASE_r.png, BASE_g.png and BASE_b.png; any other
+    /// count is numbered BASE_1.png, BASE_2.png and so on.
+    #[structopt(long = "split-out")]
+    split_out: Option<String>,
+
+    /// End each escaped orbit where its last segment crosses the escape
+    /// boundary (the --escape-shape at -b), instead of at the first point
+    /// outside it.
+    #[structopt(long = "smooth-bailout")]
+    smooth_bailout: bool,
+
+    /// The region orbits escape from: square (half-width -b), circle
+    /// (radius -b), diamond (|re| + |im| > -b), or custom, the
+    /// --escape-polygon scaled by -b. The overlay and --clip-to-set then use
+    /// the same shape, sized to hold the radius 2 circle. Without this,
+    /// orbits escape the square and the overlay the circle.
+    #[structopt(long = "escape-shape")]
+    escape_shape: Option<escape::EscapeShape>,
+
+    /// The polygon for --escape-shape custom: `re im` vertices, one per
+    /// line, in order around the origin.
+    #[structopt(long = "escape-polygon")]
+    escape_polygon: Option<String>,
+
+    /// The polygon loaded from --escape-polygon.
+    #[structopt(skip)]
+    #[serde(skip)]
+    bailout_polygon: Option<Arc<escape::Polygon>>,
+
+    /// After saving, compare the render with this reference image and print
+    /// the mean and max error per channel, exiting with an error if the mean
+    /// is above --tolerance.
+    #[structopt(long = "compare")]
+    compare: Option<String>,
+
+    /// The largest mean error per channel, from 0 to 255, that --compare
+    /// accepts.
+    #[structopt(long = "tolerance", default_value = "0")]
+    tolerance: f64,
+
+    /// Also write the --compare difference as a greyscale image, stretched so
+    /// the largest difference is white.
+    #[structopt(long = "diff-out", requires = "compare")]
+    diff_out: Option<String>,
+
+    /// Only sample the part of the grid under the view, plus --view-margin,
+    /// instead of all of `[-b, b]`. Orbits starting further out that would
+    /// have wandered into the view are lost, so widen the margin if the
+    /// render looks thinner than a full one.
+    #[structopt(long = "view-sampling")]
+    view_sampling: bool,
+
+    /// How far --view-sampling reaches past each edge of the view, as a
+    /// fraction of the view's width.
+    #[structopt(long = "view-margin", default_value = "0.5")]
+    view_margin: f64,
+
+    /// Experimental. For each escaped orbit, also draw a time-reversed orbit
+    /// of the same length, iterating the inverse map back from zero. Only
+    /// valid for power 2, and --layers with another power skip it.
+    #[structopt(long = "double", conflicts_with = "powers")]
+    double: bool,
+
+    /// Which square root --double takes at each backward step: principal,
+    /// alternate (switching sign every step), or random (using --seed).
+    #[structopt(long = "double-branch", default_value = "principal")]
+    double_branch: Branch,
+
+    /// Compression for TIFF output: none, lzw, or deflate.
+    #[structopt(long = "tiff-compression")]
+    tiff_compression: Option<output::TiffCompression>,
+
+    /// Also cut the image into a Deep Zoom (DZI) tile pyramid in this
+    /// directory, as `<name>.dzi` and `<name>_files`, for browser viewers.
+    /// Each level is the one above halved; see the deepzoom module.
+    #[structopt(
+        long = "deepzoom-out",
+        alias = "tile-output",
+        conflicts_with_all = &["interactive", "vary", "bounds-sweep"]
+    )]
+    deepzoom_out: Option<String>,
+
+    /// Quality for JPEG output, from 1 to 100.
+    #[structopt(long = "jpeg-quality")]
+    jpeg_quality: Option<u8>,
+}
+
+impl Args {
+    /// The opacity of a single segment, scaled up to compensate for
+    /// --sample-stride, and by the --auto-exposure factor.
+    fn base_opacity(&self) -> u16 {
+        let stride = self.sample_stride.min(u16::MAX as usize) as u16;
+        let opacity = self.opacity.saturating_mul(stride);
+        if self.exposure == 1.0 {
+            opacity
+        } else {
+            (opacity as f64 * self.exposure)
+                .round()
+                .clamp(1.0, u16::MAX as f64) as u16
+        }
+    }
+
+    /// The region orbits escape from, at a size of -b.
+    fn bailout(&self) -> escape::Bailout<'_> {
+        escape::Bailout::new(
+            self.escape_shape.unwrap_or(escape::EscapeShape::Square),
+            self.bailout_polygon.as_deref(),
+        )
+    }
+
+    /// Whether `z` has escaped, for the overlay and --clip-to-set. Without
+    /// --escape-shape this is the radius 2 circle, and otherwise the shape
+    /// just big enough to hold it.
+    fn overlay_escaped(&self, z: Complex64) -> bool {
+        match self.escape_shape {
+            None => z.norm_sqr() > 4.0,
+            Some(_) => {
+                let bailout = self.bailout();
+                bailout.outside(z, 2.0 / bailout.inradius())
+            }
+        }
+    }
+
+    /// The grid spacing along the real and imaginary axes.
+    fn grid_spacing(&self) -> (f64, f64) {
+        (
+            self.delta_re.unwrap_or(self.delta),
+            self.delta_im.unwrap_or(self.delta),
+        )
+    }
+
+    /// How much to scale opacity by so the average hits per pixel match the
+    /// default size, zoom and delta. Each orbit covers a length proportional
+    /// to the zoom in pixels, and there are 1/delta^2 orbits spread over
+    /// size^2 pixels.
+    fn auto_exposure(&self) -> f64 {
+        const REFERENCE: f64 = 2000.0 * 2000.0 * 0.01 * 0.01 / 900.0;
+        let size = self.size as f64;
+        let (delta_re, delta_im) = self.grid_spacing();
+        size * size * delta_re * delta_im / self.zoom / REFERENCE
+    }
+
+    /// The arguments to draw the traces with under --render-scale, or `None`
+    /// when drawing at the output size.
+    fn scaled_for_render(&self) -> Option<Args> {
+        if self.render_scale <= 1 {
+            return None;
+        }
+
+        let mut scaled = self.clone();
+        scaled.size *= self.render_scale;
+        scaled.zoom *= self.render_scale as f64;
+        // A line covers N times as many pixels, but each output pixel then
+        // averages N^2 of them.
+        scaled.opacity = scaled
+            .opacity
+            .saturating_mul(self.render_scale.min(u16::MAX as u32) as u16);
+        scaled.point_size = self.point_size.map(|r| r * self.render_scale as f32);
+        Some(scaled)
+    }
+
+    /// Cuts an orbit iteration limit down for --fast-preview.
+    fn preview_limit(&self, limit: usize) -> usize {
+        if self.fast_preview {
+            (limit / FAST_PREVIEW_LIMIT_DIVISOR).max(1)
+        } else {
+            limit
+        }
+    }
+
+    /// Whether `z` may be drawn under --draw-region.
+    fn in_draw_region(&self, z: Complex64) -> bool {
+        match self.draw_region[..] {
+            [re0, im0, re1, im1] => {
+                (re0.min(re1)..=re0.max(re1)).contains(&z.re)
+                    && (im0.min(im1)..=im0.max(im1)).contains(&z.im)
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether one of the colourings that accumulates a colour per segment
+    /// is on.
+    fn rgb_colouring(&self) -> bool {
+        self.rgb_phase
+            || self.trajectory_direction
+            || self.trap_attractor_cluster
+            || self.depth_colours.is_some()
+    }
+
+    /// The number of grid points handed out at a time.
+    fn work_chunk_len(&self) -> usize {
+        if self.chunk_balance {
+            (self.chunk_len / CHUNK_BALANCE_DIVISOR).max(1)
+        } else {
+            self.chunk_len
+        }
+    }
+
+    fn escape_limit(&self) -> usize {
+        self.preview_limit(self.escape_limit.unwrap_or(self.limit))
+    }
+
+    fn trap_limit(&self) -> usize {
+        self.preview_limit(self.trap_limit.unwrap_or(self.limit))
+    }
+
+    fn mobius_coefficients(&self) -> [Complex64; 4] {
+        let p = &self.warp_params;
+        match p.len() {
+            4 => [0, 1, 2, 3].map(|i| Complex64::new(p[i], 0.0)),
+            8 => [0, 1, 2, 3].map(|i| Complex64::new(p[2 * i], p[2 * i + 1])),
+            _ => [1.0, 0.0, 0.0, 1.0].map(|v| Complex64::new(v, 0.0)),
+        }
+    }
+}
+
+struct ArrWindows<'a, T, const N: usize>(&'a [T]);
+impl<'a, T, const N: usize> Iterator for ArrWindows<'a, T, N> {
+    type Item = &'a [T; N];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.0.get(..N)?;
+        self.0 = self.0.get(1..)?;
+        next.try_into().ok()
+    }
+}
+
+/// `z` raised to `pow`. The small integer powers are multiplied out, which is
+/// much cheaper than `powf` and doesn't go through its log and exp.
+fn power<T: Float>(z: Complex<T>, pow: T) -> Complex<T> {
+    let two = T::one() + T::one();
+    if pow == two {
+        z * z
+    } else if pow == two + T::one() {
+        z * z * z
+    } else if pow == two * two {
+        let z2 = z * z;
+        z2 * z2
+    } else {
+        z.powf(pow)
+    }
+}
+
+fn mandelbrot(z: Complex64, (x, y): (f64, f64), args: &Args) -> Complex64 {
+    power(z, args.pow) + Complex64::new(x, y)
+}
+
+/// Maps a point to the pixel containing it. Pixel `x` covers `[x, x + 1)`, so
+/// this floors rather than truncating towards zero.
+fn to_image_coord(z: Complex64, args: &Args) -> (i32, i32) {
+    let (pos_x, pos_y) = to_image_pos(z, args);
+    (pos_x.floor() as i32, pos_y.floor() as i32)
+}
+
+/// Maps a point to its exact position on the image, in pixels.
+fn to_image_pos(z: Complex64, args: &Args) -> (f64, f64) {
+    let half = args.size as f64 / 2.0;
+    let (pos_x, pos_y) = match args.projection {
+        Projection::Planar => (
+            half + (z.re + args.re_off) * args.zoom,
+            half + (z.im + args.im_off) * args.zoom,
+        ),
+        Projection::Stereographic => {
+            // The point's angle from the south pole of the Riemann sphere,
+            // as a fraction of the disk radius.
+            let v = Complex64::new(z.re + args.re_off, z.im + args.im_off) * args.zoom / half;
+            let r = v.norm();
+            let scale = if r == 0.0 {
+                0.0
+            } else {
+                FRAC_2_PI * (FRAC_PI_2 * r).atan() / r
+            };
+            (half + v.re * scale * half, half + v.im * scale * half)
+        }
+    };
+    (pos_x, pos_y)
+}
+
+/// Maps a pixel to the complex coordinate at its centre, so it round trips
+/// through `to_image_coord`. Pixels outside the stereographic disk have no
+/// coordinate.
+fn to_complex_coord(x: u32, y: u32, args: &Args) -> Option<Complex64> {
+    let half = args.size as f64 / 2.0;
+    let pos = Complex64::new(x as f64 + 0.5 - half, y as f64 + 0.5 - half);
+    let offset = Complex64::new(args.re_off, args.im_off);
+
+    match args.projection {
+        Projection::Planar => Some(pos / args.zoom - offset),
+        Projection::Stereographic => {
+            let w = pos / half;
+            let rho = w.norm();
+            if rho >= 1.0 {
+                return None;
+            }
+
+            let scale = if rho == 0.0 {
+                0.0
+            } else {
+                FRAC_2_PI * (FRAC_PI_2 * rho).tan() / rho
+            };
+            Some(w * scale * half / args.zoom - offset)
+        }
+    }
+}
+
+/// How a stroke of opacity `a`, covering `alpha` of the pixel, combines with
+/// the existing density `b`.
+type Mix = fn(Luma<u32>, Luma<u32>, f32) -> Luma<u32>;
+
+/// The fixed point optical depth of an opacity, from 0 to `u16::MAX`.
+fn depth(opacity: f32) -> u32 {
+    let max = u16::MAX as f32;
+    // Fully opaque would be infinitely deep, so it's capped at just past the
+    // point where it rounds to opaque.
+    let transmitted = (1.0 - opacity / max).max(0.5 / max);
+    (-transmitted.ln() * DEPTH_SCALE).round() as u32
+}
+
+fn blend(a: Luma<u32>, b: Luma<u32>, alpha: f32) -> Luma<u32> {
+    Luma([b[0].saturating_add(depth(a[0] as f32 * alpha))])
+}
+
+/// Keeps the more opaque of the two, for --per-trace-max.
+fn blend_max(a: Luma<u32>, b: Luma<u32>, alpha: f32) -> Luma<u32> {
+    Luma([b[0].max(depth(a[0] as f32 * alpha))])
+}
+
+/// The iteration the orbit of `c` escapes at, if it does within the limit.
+/// This is the escape test used by the overlay, and to decide set membership
+/// for --clip-to-set.
+fn escape_time(c: Complex64, args: &Args) -> Option<usize> {
+    let mut z = Complex64::default();
+    for i in 0..args.limit {
+        z = mandelbrot(z, (c.re, c.im), args);
+
+        if args.overlay_escaped(z) {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+fn escapes(c: Complex64, args: &Args) -> bool {
+    escape_time(c, args).is_some()
+}
+
+/// Shades an escape time on a log scale, so the many quickly escaping points
+/// near the edge of the bounds don't use up the whole gradient. Points in the
+/// set are black.
+fn escape_colour(time: Option<usize>, palette: &Gradient, args: &Args) -> Rgba<u8> {
+    match time {
+        Some(i) => palette.sample((i as f64 + 1.0).ln() / (args.limit as f64 + 1.0).ln()),
+        None => Rgba([0, 0, 0, 255]),
+    }
+}
+
+/// Averages `ln|f'(z)|` over the orbit of `c`, where `f'(z) = 2z` for the
+/// power 2 set. Negative values mean the orbit is attracted to a cycle.
+fn lyapunov_exponent(c: Complex64, args: &Args) -> f64 {
+    let mut z = Complex64::default();
+    let mut sum = 0.0;
+    let mut count = 0;
+    for _ in 0..args.limit {
+        z = mandelbrot(z, (c.re, c.im), args);
+        if args.overlay_escaped(z) {
+            break;
+        }
+
+        sum += (2.0 * z.norm()).ln();
+        count += 1;
+    }
+
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f64
+    }
+}
+
+/// Diverging colour map: blue for stable (negative) exponents, red for
+/// chaotic (positive) ones, fading to black at zero.
+fn lyapunov_colour(exponent: f64) -> Rgba<u8> {
+    let t = exponent.tanh();
+    let scale = |c: f64| (c * t.abs()) as u8;
+    if t < 0.0 {
+        Rgba([scale(40.0), scale(90.0), scale(255.0), 255])
+    } else {
+        Rgba([scale(255.0), scale(60.0), scale(30.0), 255])
+    }
+}
+
+/// The closest the orbit of `c` comes to the orbit trap, up until it escapes.
+fn orbit_trap_distance(c: Complex64, trap: OrbitTrap, args: &Args) -> f64 {
+    let centre = Complex64::new(args.trap_pos[0], args.trap_pos[1]);
+    let distance = |z: Complex64| match trap {
+        OrbitTrap::Point => (z - centre).norm(),
+        OrbitTrap::Cross => (z.re - centre.re).abs().min((z.im - centre.im).abs()),
+        OrbitTrap::Circle => ((z - centre).norm() - args.trap_radius).abs(),
+    };
+
+    let mut z = Complex64::default();
+    let mut closest = f64::INFINITY;
+    for _ in 0..args.limit {
+        z = mandelbrot(z, (c.re, c.im), args);
+        if args.overlay_escaped(z) {
+            break;
+        }
+
+        closest = closest.min(distance(z));
+    }
+
+    closest
+}
+
+/// Tests whether `c` is inside the main cardioid or the period-2 bulb, both of
+/// which are known to never escape for the standard (power 2) set.
+fn in_main_bulbs((x, y): (f64, f64)) -> bool {
+    let q = (x - 0.25).powi(2) + y * y;
+    let in_cardioid = q * (q + (x - 0.25)) <= 0.25 * y * y;
+    let in_bulb = (x + 1.0).powi(2) + y * y <= 0.0625;
+
+    in_cardioid || in_bulb
+}
+
+/// Estimates how far `c` is from the boundary of the power 2 set, from the
+/// derivative of its orbit. Points that don't escape within the limit are
+/// taken to be in the set, at a distance of 0.
+fn boundary_distance(c: Complex64, args: &Args) -> f64 {
+    // A large escape radius makes the estimate more accurate.
+    const RADIUS_SQR: f64 = 1e6;
+
+    let mut z = Complex64::default();
+    let mut dz = Complex64::default();
+    for _ in 0..args.limit {
+        dz = 2.0 * z * dz + 1.0;
+        z = z * z + c;
+
+        if z.norm_sqr() > RADIUS_SQR {
+            let r = z.norm();
+            return r * r.ln() / dz.norm();
+        }
+    }
+
+    0.0
+}
+
+/// Whether the cardioid/bulb test can be used to reject points without
+/// changing the output.
+fn can_reject_bulbs(args: &Args) -> bool {
+    matches!(args.mode, DrawMode::Escaped)
+        && matches!(args.warp, Warp::None)
+        && args.pow == 2.0
+        && args.bounds * args.bailout().inradius() >= 2.0
+}
+
+struct Orbit {
+    points: Vec<Complex64>,
+    /// The number of iterations it took to escape, or `None` if it was
+    /// trapped.
+    escaped_at: Option<usize>,
+}
+
+impl Orbit {
+    /// The opacity to draw this orbit's segments with.
+    fn opacity(&self, args: &Args) -> u16 {
+        if args.limit_proximity_weight {
+            let fraction = match self.escaped_at {
+                Some(i) => i as f32 / args.escape_limit() as f32,
+                None => 1.0,
+            };
+            return (args.base_opacity() as f32 * fraction.min(1.0))
+                .round()
+                .max(1.0) as u16;
+        }
+        if !args.escape_weight {
+            return args.base_opacity();
+        }
+
+        // Trapped orbits are weighted as if they escaped at the limit.
+        let iterations = self.escaped_at.unwrap_or(args.trap_limit() + 1);
+        (args.base_opacity() as f32 / iterations as f32)
+            .round()
+            .max(1.0) as u16
+    }
+}
+
+fn warp_coordinate((x, y): (f64, f64), args: &Args) -> (f64, f64) {
+    let z = Complex64::new(x, y);
+    let w = match args.warp {
+        Warp::None => return (x, y),
+        Warp::Exp => z.exp(),
+        Warp::Log => z.ln(),
+        Warp::Mobius => {
+            let [a, b, c, d] = args.mobius_coefficients();
+            (a * z + b) / (c * z + d)
+        }
+    };
+
+    (w.re, w.im)
+}
+
+/// How much --fast-preview divides the orbit limits by.
+const FAST_PREVIEW_LIMIT_DIVISOR: usize = 4;
+/// How much --chunk-balance divides --chunk_len by.
+const CHUNK_BALANCE_DIVISOR: usize = 64;
+
+/// How an orbit ended, using --escape-limit and --trap-limit.
+enum Fate {
+    Escaped(usize),
+    Trapped,
+    /// Escaped after the escape limit, but before reaching the trap limit.
+    Unclassified,
+}
+
+/// Iterates `coord`, handing every orbit point to `visit`, for as long as the
+/// current draw mode needs to classify it.
+fn run_orbit(coord: (f64, f64), args: &Args, visit: impl FnMut(Complex64)) -> Fate {
+    if args.fast_preview {
+        run_orbit_as::<f32>(coord, args, visit)
+    } else {
+        run_orbit_as::<f64>(coord, args, visit)
+    }
+}
+
+/// `run_orbit`, iterating in the float type `T`.
+fn run_orbit_as<T: Float>(
+    coord: (f64, f64),
+    args: &Args,
+    mut visit: impl FnMut(Complex64),
+) -> Fate {
+    let cast = |v: f64| T::from(v).unwrap();
+    let (x, y) = warp_coordinate(coord, args);
+    let c = Complex::new(cast(x), cast(y));
+    let pow = cast(args.pow);
+    let bounds = cast(args.bounds);
+    let mut visit = |z: Complex<T>| {
+        visit(Complex64::new(
+            z.re.to_f64().unwrap(),
+            z.im.to_f64().unwrap(),
+        ));
+    };
+
+    let bailout = args.bailout();
+    let escape_limit = args.escape_limit();
+    let limit = match args.mode {
+        // Anything still going after the escape limit can't be drawn.
+        DrawMode::Escaped => escape_limit,
+        _ => args.trap_limit(),
+    };
+
+    let mut z = power(Complex::new(T::zero(), T::zero()), pow) + c;
+    visit(z);
+
+    for i in 1..=limit {
+        z = power(z, pow) + c;
+        // A large power can overflow to infinity or NaN in a single step,
+        // which can't be drawn and never compares as outside the bounds, so
+        // it escapes here without being visited.
+        let finite = z.norm_sqr().is_finite();
+        if finite {
+            visit(z);
+        }
+
+        if !finite || bailout.outside(z, bounds) {
+            return if i <= escape_limit {
+                Fate::Escaped(i)
+            } else {
+                Fate::Unclassified
+            };
+        }
+    }
+
+    if limit < args.trap_limit() {
+        Fate::Unclassified
+    } else {
+        Fate::Trapped
+    }
+}
+
+fn wanted_by_mode(fate: &Fate, args: &Args) -> bool {
+    use DrawMode::*;
+    matches!(
+        (args.mode, fate),
+        (All, Fate::Escaped(_) | Fate::Trapped)
+            | (Escaped, Fate::Escaped(_))
+            | (Trapped, Fate::Trapped)
+    )
+}
+
+fn iterate_coordinate(coord: (f64, f64), args: &Args) -> Option<Orbit> {
+    let mut points = Vec::with_capacity(args.trap_limit().max(args.escape_limit()) + 1);
+    let fate = run_orbit(coord, args, |z| points.push(z));
+
+    let escaped_at = match fate {
+        Fate::Escaped(i) => Some(i),
+        _ => None,
+    };
+    if escaped_at.is_some() && args.smooth_bailout {
+        if let [.., inside, outside] = points.as_mut_slice() {
+            *outside = args.bailout().crossing(*inside, *outside, args.bounds);
+        }
+    }
+    (wanted_by_mode(&fate, args) && points.len() >= args.min_orbit_length)
+        .then_some(Orbit { points, escaped_at })
+}
+
+/// The time-reversed orbit drawn by --double for an escaped orbit: starting
+/// from zero, each point is a preimage `±sqrt(z - c)` of the one before,
+/// with the sign picked by --double-branch.
+fn reversed_orbit(coord: (f64, f64), orbit: &Orbit, args: &Args) -> Option<Orbit> {
+    if args.pow != 2.0 {
+        return None;
+    }
+    orbit.escaped_at?;
+
+    let (x, y) = warp_coordinate(coord, args);
+    let c = Complex64::new(x, y);
+    let key = hash(x.to_bits() ^ hash(y.to_bits() ^ hash(args.seed)));
+
+    let mut z = Complex64::default();
+    let points = (0..orbit.points.len() as u64)
+        .map(|i| {
+            let root = (z - c).sqrt();
+            let negate = match args.double_branch {
+                Branch::Principal => false,
+                Branch::Alternate => i % 2 == 1,
+                Branch::Random => hash(key ^ i) & 1 == 1,
+            };
+            z = if negate { -root } else { root };
+            z
+        })
+        .collect();
+
+    Some(Orbit {
+        points,
+        escaped_at: orbit.escaped_at,
+    })
+}
+
+/// The orbit and its --symmetrize copies, rotated about the view centre.
+fn symmetric_copies(orbit: Orbit, args: &Args) -> Vec<Orbit> {
+    let centre = Complex64::new(-args.re_off, -args.im_off);
+    let copies = (1..args.symmetrize).map(|k| {
+        let turn = Complex64::from_polar(
+            1.0,
+            std::f64::consts::TAU * k as f64 / args.symmetrize as f64,
+        );
+        Orbit {
+            points: orbit
+                .points
+                .iter()
+                .map(|&z| centre + (z - centre) * turn)
+                .collect(),
+            escaped_at: orbit.escaped_at,
+        }
+    });
+
+    let mut all: Vec<_> = copies.collect();
+    all.insert(0, orbit);
+    all
+}
+
+/// Same classification as `iterate_coordinate`, without keeping the orbit.
+fn would_draw(coord: (f64, f64), args: &Args) -> bool {
+    let mut len = 0;
+    let fate = run_orbit(coord, args, |_| len += 1);
+    wanted_by_mode(&fate, args) && len >= args.min_orbit_length
+}
+
+/// For the RGB colourings (--rgb-phase and --trajectory-direction), each
+/// pixel's sum of segment colours scaled by the weight they were drawn
+/// with, followed by the total weight.
+type ColourSums = Vec<[f32; 4]>;
+
+/// The traces of some orbits, and their colour sums with an RGB colouring.
+#[derive(Clone)]
+struct Canvas {
+    density: Density,
+    colour: Option<ColourSums>,
+}
+
+impl Canvas {
+    fn new(args: &Args) -> Self {
+        Self {
+            density: Density::new(args.size, args.size),
+            colour: args
+                .rgb_colouring()
+                .then(|| vec![[0.0; 4]; args.size as usize * args.size as usize]),
+        }
+    }
+
+    fn merge(mut self, other: &Canvas) -> Self {
+        self.density
+            .pixels_mut()
+            .zip(other.density.pixels())
+            .for_each(|(o, i)| o[0] = o[0].saturating_add(i[0]));
+        if let (Some(colour), Some(other)) = (&mut self.colour, &other.colour) {
+            colour.iter_mut().zip(other).for_each(|(o, i)| {
+                for c in 0..4 {
+                    o[c] += i[c];
+                }
+            });
+        }
+
+        self
+    }
+
+    /// The traces in white, with the alpha the density adds up to.
+    fn image(&self) -> Image {
+        let (w, h) = self.density.dimensions();
+        let max = u16::MAX as f64;
+        Image::from_fn(w, h, |x, y| match self.density.get_pixel(x, y)[0] {
+            0 => LumaA([0, 0]),
+            d => {
+                let alpha = 1.0 - (-(d as f64) / DEPTH_SCALE as f64).exp();
+                LumaA([u16::MAX, (max * alpha).round() as u16])
+            }
+        })
+    }
+}
+
+/// A fully saturated colour with the hue given by the argument of `z`.
+fn phase_colour(z: Complex64) -> [f32; 3] {
+    let h = (z.arg() + std::f64::consts::PI) / std::f64::consts::TAU * 6.0;
+    let x = (1.0 - (h % 2.0 - 1.0).abs()) as f32;
+    match h as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+/// The longest cycle --trap-attractor-cluster looks for.
+const MAX_PERIOD: usize = 64;
+/// How close an orbit has to come back to a point to count as a cycle.
+const PERIOD_TOLERANCE: f64 = 1e-6;
+
+/// The period of the cycle a trapped orbit ends in, if it has settled.
+fn orbit_period(orbit: &Orbit) -> Option<usize> {
+    if orbit.escaped_at.is_some() {
+        return None;
+    }
+
+    let (&last, earlier) = orbit.points.split_last()?;
+    (1..=MAX_PERIOD.min(earlier.len()))
+        .find(|&p| (last - earlier[earlier.len() - p]).norm() < PERIOD_TOLERANCE)
+}
+
+/// A distinct hue for each period, stepping around the hue wheel by the
+/// golden angle. Orbits without one are white.
+fn period_colour(period: Option<usize>) -> [f32; 3] {
+    match period {
+        Some(p) => {
+            let turn = ((p - 1) as f64 * 0.381_966_011_250_105) % 1.0;
+            phase_colour(Complex64::from_polar(
+                1.0,
+                turn * std::f64::consts::TAU - std::f64::consts::PI,
+            ))
+        }
+        None => [1.0; 3],
+    }
+}
+
+/// Red for a step away from the origin, blue for a step towards it.
+fn direction_colour(from: Complex64, to: Complex64) -> [f32; 3] {
+    if to.norm_sqr() < from.norm_sqr() {
+        [0.2, 0.4, 1.0]
+    } else {
+        [1.0, 0.25, 0.1]
+    }
+}
+
+/// Adds a colour to every pixel of the aliased line from `start` to `end`,
+/// so it's counted once per pixel whatever the anti-aliasing.
+fn add_colour(
+    sums: &mut ColourSums,
+    size: u32,
+    start: (i32, i32),
+    end: (i32, i32),
+    colour: [f32; 3],
+    weight: f32,
+) {
+    let start = (start.0 as f32, start.1 as f32);
+    let end = (end.0 as f32, end.1 as f32);
+    for (x, y) in BresenhamLineIter::new(start, end) {
+        if x >= 0 && y >= 0 && (x as u32) < size && (y as u32) < size {
+            let p = &mut sums[y as usize * size as usize + x as usize];
+            for c in 0..3 {
+                p[c] += colour[c] * weight;
+            }
+            p[3] += weight;
+        }
+    }
+}
+
+fn iterate_chunk(chunk: &[(f64, f64)], mut canvas: Canvas, args: &Args) -> Canvas {
+    let image = &mut canvas.density;
+    // With --per-trace-max, each trace is drawn here first, then added to the
+    // canvas. One buffer is kept for the whole chunk, and only the pixels a
+    // trace touched are cleared after it.
+    let mut scratch = args
+        .per_trace_max
+        .then(|| Density::new(args.size, args.size));
+    let mix: Mix = if args.per_trace_max { blend_max } else { blend };
+    // The lines or points drawn by the current trace, and how far around
+    // them drawing reaches.
+    let mut touched = Vec::new();
+    let reach = match (args.aa, args.point_size) {
+        (AntiAlias::Gaussian, Some(radius)) => radius.ceil() as i32,
+        (AntiAlias::Gaussian, None) => GAUSSIAN_RADIUS,
+        _ if args.subpixel => 2,
+        _ => 1,
+    };
+
+    let traces = chunk.iter().flat_map(|&coord| {
+        let orbit = iterate_coordinate(coord, args);
+        let reversed = match &orbit {
+            Some(orbit) if args.double => reversed_orbit(coord, orbit, args),
+            _ => None,
+        };
+        orbit.into_iter().chain(reversed)
+    });
+    let traces = traces.flat_map(|orbit| symmetric_copies(orbit, args));
+
+    for orbit in traces {
+        let opacity = orbit.opacity(args);
+        let colour = Luma([opacity as u32]);
+        let weight = opacity as f32;
+        let t = orbit.points.get(args.trim_transient..).unwrap_or(&[]);
+        let orbit_rgb = args
+            .trap_attractor_cluster
+            .then(|| period_colour(orbit_period(&orbit)));
+        let target = scratch.as_mut().unwrap_or(&mut *image);
+
+        if let AntiAlias::Gaussian = args.aa {
+            for (i, &z) in t.iter().enumerate() {
+                if args.clip_to_set && escapes(z, args) || !args.in_draw_region(z) {
+                    continue;
+                }
+
+                let pos = to_image_coord(z, args);
+                match args.point_size {
+                    Some(radius) => splat_disk(target, pos, radius, colour, mix),
+                    None => splat_gaussian(target, pos, colour, mix),
+                }
+                if args.per_trace_max {
+                    touched.push((pos, pos));
+                }
+                if let Some(sums) = &mut canvas.colour {
+                    let rgb = if let Some(rgb) = orbit_rgb {
+                        rgb
+                    } else if let Some(ramp) = &args.depth_colours {
+                        ramp.at(i, t.len())
+                    } else if args.trajectory_direction {
+                        let prev = i.checked_sub(1).map_or(Complex64::default(), |j| t[j]);
+                        direction_colour(prev, z)
+                    } else {
+                        phase_colour(z)
+                    };
+                    add_colour(sums, args.size, pos, pos, rgb, weight);
+                }
+            }
+        } else {
+            for (i, &[w1, w2]) in ArrWindows(t).enumerate() {
+                if args.clip_to_set && (escapes(w1, args) || escapes(w2, args))
+                    || !(args.in_draw_region(w1) && args.in_draw_region(w2))
+                {
+                    continue;
+                }
+
+                let from_to = (to_image_pos(w1, args), to_image_pos(w2, args));
+                let (from, to) = match within_reach(from_to, args.size) {
+                    Some(line) => line,
+                    None => continue,
+                };
+                let pixel = |(x, y): (f64, f64)| (x.floor() as i32, y.floor() as i32);
+                let (start, end) = (pixel(from), pixel(to));
+                match args.aa {
+                    _ if args.subpixel => splat_line(target, from, to, colour, mix),
+                    AntiAlias::None => draw_aliased_line(target, start, end, colour, mix),
+                    _ => draw_line(target, start, end, colour, mix),
+                }
+                if args.per_trace_max {
+                    touched.push((start, end));
+                }
+                if let Some(sums) = &mut canvas.colour {
+                    let rgb = if let Some(rgb) = orbit_rgb {
+                        rgb
+                    } else if let Some(ramp) = &args.depth_colours {
+                        ramp.at(i, t.len() - 1)
+                    } else if args.trajectory_direction {
+                        direction_colour(w1, w2)
+                    } else {
+                        phase_colour(w1)
+                    };
+                    add_colour(sums, args.size, start, end, rgb, weight);
+                }
+            }
+        }
+
+        if let Some(scratch) = &mut scratch {
+            flush_trace(scratch, image, &touched, reach);
+            touched.clear();
+        }
+    }
+
+    canvas
+}
+
+/// A line drawn between two pixels, or a point when they're the same.
+type Stroke = ((i32, i32), (i32, i32));
+
+/// Adds a finished trace from the scratch buffer onto the canvas, and clears
+/// it for the next, visiting every pixel within `reach` of the lines drawn.
+fn flush_trace(scratch: &mut Density, image: &mut Density, touched: &[Stroke], reach: i32) {
+    let (w, h) = (image.width() as i32, image.height() as i32);
+    for &(start, end) in touched {
+        let start = (start.0 as f32, start.1 as f32);
+        let end = (end.0 as f32, end.1 as f32);
+        for (x, y) in BresenhamLineIter::new(start, end) {
+            for py in (y - reach).max(0)..=(y + reach).min(h - 1) {
+                for px in (x - reach).max(0)..=(x + reach).min(w - 1) {
+                    let p = scratch.get_pixel_mut(px as u32, py as u32);
+                    if p[0] > 0 {
+                        let o = image.get_pixel_mut(px as u32, py as u32);
+                        o[0] = o[0].saturating_add(p[0]);
+                        *p = Luma([0]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn plot(image: &mut Density, (x, y): (i32, i32), colour: Luma<u32>, weight: f32, mix: Mix) {
+    if x >= 0 && y >= 0 && (x as u32) < image.width() && (y as u32) < image.height() {
+        let pixel = image.get_pixel_mut(x as u32, y as u32);
+        *pixel = mix(colour, *pixel, weight);
+    }
+}
+
+fn draw_aliased_line(
+    image: &mut Density,
+    start: (i32, i32),
+    end: (i32, i32),
+    colour: Luma<u32>,
+    mix: Mix,
+) {
+    let start = (start.0 as f32, start.1 as f32);
+    let end = (end.0 as f32, end.1 as f32);
+
+    for point in BresenhamLineIter::new(start, end) {
+        plot(image, point, colour, 1.0, mix);
+    }
+}
+
+/// Draws the line from `from` to `to` for --subpixel. It's sampled at most
+/// a pixel apart, and each sample is split bilinearly between the four
+/// pixels around it, weighted so the whole line deposits its length.
+fn splat_line(image: &mut Density, from: (f64, f64), to: (f64, f64), colour: Luma<u32>, mix: Mix) {
+    let size = image.width().max(image.height()) as f64;
+    let (from, to) = match clip_line(from, to, -1.0, size + 1.0) {
+        Some(clipped) => clipped,
+        None => return,
+    };
+
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = dx.hypot(dy);
+    let steps = length.ceil().max(1.0);
+    let weight = length / steps;
+    for i in 0..steps as usize {
+        let t = (i as f64 + 0.5) / steps;
+        // Relative to the pixel centres, which are at x + 0.5.
+        let (u, v) = (from.0 + t * dx - 0.5, from.1 + t * dy - 0.5);
+        let (x, y) = (u.floor(), v.floor());
+        let (fx, fy) = (u - x, v - y);
+
+        for (ox, oy, w) in [
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ] {
+            let pos = (x as i32 + ox, y as i32 + oy);
+            plot(image, pos, colour, (weight * w) as f32, mix);
+        }
+    }
+}
+
+/// How many image widths past the edge a line can reach before it's clipped
+/// for drawing. Ordinary escapes stay well inside this and are drawn as they
+/// are, but one step of a large power can throw a point so far out that
+/// stepping along the line to it would take billions of pixels.
+const LINE_REACH: f64 = 16.0;
+
+/// The line between two image positions, clipped to `LINE_REACH` around an
+/// image of `size` if it reaches further, or `None` if nothing of it is left.
+fn within_reach(
+    (from, to): ((f64, f64), (f64, f64)),
+    size: u32,
+) -> Option<((f64, f64), (f64, f64))> {
+    let reach = LINE_REACH * size as f64;
+    let (lo, hi) = (-reach, size as f64 + reach);
+    let inside = |(x, y): (f64, f64)| (lo..=hi).contains(&x) && (lo..=hi).contains(&y);
+    if inside(from) && inside(to) {
+        return Some((from, to));
+    }
+    clip_line(from, to, lo, hi)
+}
+
+/// Clips the line to the square `[lo, hi]` on both axes, by Liang-Barsky.
+fn clip_line(
+    from: (f64, f64),
+    to: (f64, f64),
+    lo: f64,
+    hi: f64,
+) -> Option<((f64, f64), (f64, f64))> {
+    if !(from.0.is_finite() && from.1.is_finite() && to.0.is_finite() && to.1.is_finite()) {
+        return None;
+    }
+
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    for (p, q) in [
+        (-dx, from.0 - lo),
+        (dx, hi - from.0),
+        (-dy, from.1 - lo),
+        (dy, hi - from.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+
+    let at = |t: f64| (from.0 + t * dx, from.1 + t * dy);
+    Some((at(t0), at(t1)))
+}
+
+const GAUSSIAN_RADIUS: i32 = 2;
+
+/// Splats a small gaussian kernel (sigma of one pixel) centred on `centre`.
+fn splat_gaussian(image: &mut Density, (cx, cy): (i32, i32), colour: Luma<u32>, mix: Mix) {
+    for dy in -GAUSSIAN_RADIUS..=GAUSSIAN_RADIUS {
+        for dx in -GAUSSIAN_RADIUS..=GAUSSIAN_RADIUS {
+            let weight = (-((dx * dx + dy * dy) as f32) / 2.0).exp();
+            plot(image, (cx + dx, cy + dy), colour, weight, mix);
+        }
+    }
+}
+
+/// Draws a filled disk of `radius` pixels centred on `centre`, with a one
+/// pixel soft edge.
+fn splat_disk(image: &mut Density, (cx, cy): (i32, i32), radius: f32, colour: Luma<u32>, mix: Mix) {
+    let reach = radius.ceil() as i32;
+
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let distance = ((dx * dx + dy * dy) as f32).sqrt();
+            let weight = (radius + 1.0 - distance).clamp(0.0, 1.0);
+            if weight > 0.0 {
+                plot(image, (cx + dx, cy + dy), colour, weight, mix);
+            }
+        }
+    }
+}
+
+/// Bins every orbit point by its real or imaginary part, using one bin per
+/// pixel across the image.
+fn axis_histogram_chunk(chunk: &[(f64, f64)], args: &Args) -> Vec<u64> {
+    let mut bins = vec![0; args.size as usize];
+    let traces = chunk
+        .iter()
+        .filter_map(|&coord| iterate_coordinate(coord, args));
+
+    for orbit in traces {
+        for &z in &orbit.points {
+            let (x, y) = to_image_coord(z, args);
+            let bin = match args.axis {
+                Axis::Re => x,
+                Axis::Im => y,
+            };
+
+            if let Some(b) = usize::try_from(bin).ok().and_then(|b| bins.get_mut(b)) {
+                *b += 1;
+            }
+        }
+    }
+
+    bins
+}
+
+fn write_axis_histogram(path: &str, bins: &[u64], args: &Args) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let label = match args.axis {
+        Axis::Re => "re",
+        Axis::Im => "im",
+    };
+    writeln!(out, "{},count", label)?;
+
+    for (i, count) in bins.iter().enumerate() {
+        // --axis-hist-out is always planar.
+        let z = to_complex_coord(i as u32, i as u32, args).unwrap();
+        let pos = match args.axis {
+            Axis::Re => z.re,
+            Axis::Im => z.im,
+        };
+        writeln!(out, "{},{}", pos, count)?;
+    }
+
+    out.flush()
+}
+
+/// Draws the traces in `image` over `base`, in white or in the per-pixel
+/// colours of `tint`.
+fn to_u8_image(
+    image: &Image,
+    tint: Option<&[[u8; 3]]>,
+    base: Option<RgbaImage>,
+    args: &Args,
+) -> RgbaImage {
+    // A palette covers the plain black background completely, but is
+    // blended by density over anything else.
+    let opaque_palette = base.is_none() && !args.transparent_bg;
+    let mut out = base.unwrap_or_else(|| {
+        let alpha = if args.transparent_bg { 0 } else { 255 };
+        RgbaImage::from_pixel(image.width(), image.height(), Rgba([0, 0, 0, alpha]))
+    });
+
+    if args.heatmap {
+        let gradient = args.palette.clone().unwrap_or_else(Gradient::heat);
+        heatmap::paint(&mut out, image, &gradient);
+    } else {
+        let luma = match (args.alpha_curve, args.output_srgb) {
+            (AlphaCurve::Linear, false) => dither::quantize(image, args.dither),
+            (curve, srgb) => {
+                let max = u16::MAX as f64;
+                let mut shaped = image.clone();
+                shaped.pixels_mut().for_each(|p| {
+                    let mut v = curve.apply(p[0] as f64 / max);
+                    if srgb {
+                        v = srgb_encode(v);
+                    }
+                    p[0] = (max * v).round() as u16;
+                });
+                dither::quantize(&shaped, args.dither)
+            }
+        };
+        out.pixels_mut()
+            .enumerate()
+            .zip(luma)
+            .for_each(|((n, o), i)| match (&args.palette, tint) {
+                (Some(palette), None) => {
+                    let [r, g, b, _] = palette.sample(i as f64 / 255.0).0;
+                    o.blend(&Rgba([r, g, b, if opaque_palette { 255 } else { i }]));
+                }
+                _ => {
+                    let [r, g, b] = tint.map_or([255; 3], |t| t[n]);
+                    o.blend(&Rgba([r, g, b, i]));
+                }
+            });
+    }
+
+    if tint.is_some() && args.normalize_per_channel {
+        normalize_channels(&mut out);
+    }
+
+    if args.temperature != NEUTRAL_TEMPERATURE {
+        let (red, blue) = white_balance(args.temperature);
+        out.pixels_mut().for_each(|p| {
+            p[0] = (p[0] as f64 * red).min(255.0) as u8;
+            p[2] = (p[2] as f64 * blue).min(255.0) as u8;
+        });
+    }
+
+    if args.display_gamma != 1.0 {
+        let lut: Vec<u8> = (0..=255)
+            .map(|v| (255.0 * (v as f64 / 255.0).powf(1.0 / args.display_gamma)).round() as u8)
+            .collect();
+        out.pixels_mut().for_each(|p| {
+            for c in &mut p.0[..3] {
+                *c = lut[*c as usize];
+            }
+        });
+    }
+
+    out
+}
+
+/// The --mb overlay, iterated to --overlay-limit.
+fn mandelbrot_overlay(args: &Args) -> RgbaImage {
+    let args = &Args {
+        limit: args.overlay_limit.unwrap_or(args.limit),
+        ..args.clone()
+    };
+    let overlay_palette = Gradient::fire();
+
+    // Rows are shared out between threads. Each pixel only depends on its
+    // own coordinate, so this matches a serial fill exactly.
+    let mut image = RgbaImage::new(args.size, args.size);
+    image
+        .par_chunks_mut(args.size as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let colour = overlay_colour(x as u32, y as u32, &overlay_palette, args);
+                pixel.copy_from_slice(&colour.0);
+            }
+        });
+    image
+}
+
+/// The overlay's colour for pixel `(x, y)`.
+fn overlay_colour(x: u32, y: u32, overlay_palette: &Gradient, args: &Args) -> Rgba<u8> {
+    let cmpl = match to_complex_coord(x, y, args) {
+        Some(cmpl) => cmpl,
+        None => return Rgba([0, 0, 0, 255]),
+    };
+    // Everything but the Lyapunov and orbit trap colourings draws the set in
+    // black, so the main cardioid and bulb needn't be iterated.
+    let skip_bulbs = args.pow == 2.0 && !args.lyapunov && args.orbit_trap.is_none();
+    if skip_bulbs && in_main_bulbs((cmpl.re, cmpl.im)) {
+        return Rgba([0, 0, 0, 255]);
+    }
+
+    if args.lyapunov {
+        lyapunov_colour(lyapunov_exponent(cmpl, args))
+    } else if let Some(trap) = args.orbit_trap {
+        let distance = orbit_trap_distance(cmpl, trap, args);
+        overlay_palette.sample((-4.0 * distance).exp())
+    } else if args.escape_map {
+        escape_colour(escape_time(cmpl, args), overlay_palette, args)
+    } else if args.mandelbrot_distance_shading {
+        distance_shade(boundary_distance(cmpl, args), args)
+    } else if escapes(cmpl, args) {
+        Rgba([128, 0, 0, 255])
+    } else {
+        Rgba([0, 0, 0, 255])
+    }
+}
+
+/// The --mandelbrot-distance-shading colour of an overlay pixel, which fades
+/// the escaped fill out with distance from the boundary. Points in the set
+/// have a distance of 0 and are black.
+fn distance_shade(distance: f64, args: &Args) -> Rgba<u8> {
+    if distance == 0.0 {
+        return Rgba([0, 0, 0, 255]);
+    }
+
+    let pixels = distance * args.zoom;
+    let fade = (-pixels / args.overlay_falloff.unwrap_or(8.0)).exp();
+    Rgba([(128.0 * fade).round() as u8, 0, 0, 255])
+}
+
+/// An opaque --bg-gradient image the size of the render.
+fn gradient_background(ramp: &ColourRamp, args: &Args) -> RgbaImage {
+    let half = args.size as f64 / 2.0;
+    let (sin, cos) = args
+        .bg_gradient_angle
+        .unwrap_or(90.0)
+        .to_radians()
+        .sin_cos();
+    // Half the extent of the image along the gradient, so a linear gradient
+    // runs from one corner's projection to the opposite one's.
+    let reach = half * (sin.abs() + cos.abs());
+
+    RgbaImage::from_fn(args.size, args.size, |x, y| {
+        let (dx, dy) = (x as f64 + 0.5 - half, y as f64 + 0.5 - half);
+        let t = if args.bg_gradient_radial {
+            dx.hypot(dy) / (half * SQRT_2)
+        } else {
+            (dx * cos + dy * sin) / (2.0 * reach) + 0.5
+        };
+        let [r, g, b] = ramp.lerp(t.clamp(0.0, 1.0) as f32);
+        Rgba([r, g, b, 1.0].map(|v| (v * 255.0).round() as u8))
+    })
+}
+
+/// The sRGB transfer function, from linear light in `[0, 1]` to the encoded
+/// value.
+fn srgb_encode(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The inverse of `srgb_encode`.
+fn srgb_decode(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Stretches each colour channel separately so its maximum is 255.
+fn normalize_channels(image: &mut RgbaImage) {
+    let mut max = [0_u8; 3];
+    for p in image.pixels() {
+        for c in 0..3 {
+            max[c] = max[c].max(p[c]);
+        }
+    }
+
+    image.pixels_mut().for_each(|p| {
+        for c in 0..3 {
+            if max[c] > 0 {
+                p[c] = (p[c] as f32 * 255.0 / max[c] as f32).round() as u8;
+            }
+        }
+    });
+}
+
+/// Applies `v^(1/gamma)` to the normalized luma, so a gamma above 1 lifts
+/// faint densities.
+fn apply_density_gamma(image: &mut Image, gamma: f64) {
+    let max = u16::MAX as f64;
+    image.pixels_mut().for_each(|p| {
+        p[0] = (max * (p[0] as f64 / max).powf(1.0 / gamma)).round() as u16;
+    });
+}
+
+const NEUTRAL_TEMPERATURE: f64 = 6500.0;
+
+/// Approximate red and blue of a blackbody at `kelvin`, from Tanner
+/// Helland's curve fit of the CIE 1964 data.
+fn blackbody_red_blue(kelvin: f64) -> (f64, f64) {
+    let t = kelvin / 100.0;
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+
+    (red.clamp(0.0, 255.0), blue.clamp(0.0, 255.0))
+}
+
+/// Red and blue multipliers that tint neutral (6500K) white to `kelvin`.
+fn white_balance(kelvin: f64) -> (f64, f64) {
+    let (red, blue) = blackbody_red_blue(kelvin);
+    let (neutral_red, neutral_blue) = blackbody_red_blue(NEUTRAL_TEMPERATURE);
+    (red / neutral_red, blue / neutral_blue)
+}
+
+/// Reflects `image` into a larger symmetric image, with the original in the
+/// top left corner.
+fn mirror(image: RgbaImage, mirror_x: bool, mirror_y: bool) -> RgbaImage {
+    use image::imageops::{flip_horizontal, flip_vertical, replace};
+
+    let (width, height) = image.dimensions();
+    let mut out = RgbaImage::new(
+        width * if mirror_x { 2 } else { 1 },
+        height * if mirror_y { 2 } else { 1 },
+    );
+
+    replace(&mut out, &image, 0, 0);
+    if mirror_x {
+        replace(&mut out, &flip_horizontal(&image), width, 0);
+    }
+    if mirror_y {
+        replace(&mut out, &flip_vertical(&image), 0, height);
+    }
+    if mirror_x && mirror_y {
+        let flipped = flip_vertical(&flip_horizontal(&image));
+        replace(&mut out, &flipped, width, height);
+    }
+
+    out
+}
+
+fn composite_images(paths: &[String]) -> Result<RgbaImage, String> {
+    let mut layers = paths.iter().map(|path| {
+        image::open(path)
+            .map(|i| i.to_rgba8())
+            .map_err(|e| format!("Failed to open '{}': {}", path, e))
+    });
+
+    let mut out = layers.next().ok_or("No images to composite")??;
+    for (layer, path) in layers.zip(&paths[1..]) {
+        let layer = layer?;
+        if layer.dimensions() != out.dimensions() {
+            return Err(format!(
+                "'{}' is {}x{}, expected {}x{}",
+                path,
+                layer.width(),
+                layer.height(),
+                out.width(),
+                out.height()
+            ));
+        }
+
+        out.pixels_mut()
+            .zip(layer.pixels())
+            .for_each(|(o, i)| o.blend(i));
+    }
+
+    Ok(out)
+}
+
+/// Inserts `.partial` before the extension, so `image.png` becomes
+/// `image.partial.png`.
+fn partial_path(path: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.partial.{}", stem, ext.to_string_lossy()),
+        None => format!("{}.partial", stem),
+    };
+
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[derive(Serialize)]
+struct RenderRecord<'a> {
+    version: &'static str,
+    args: &'a Args,
+    samples: usize,
+    duration_secs: f64,
+    /// Whether the render was cut short by Ctrl-C or --max-time.
+    partial: bool,
+    /// The arguments the render was started with, for --info.
+    command: Vec<String>,
+}
+
+fn write_render_record(image_path: &str, record: &RenderRecord) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(image_path).with_extension("json");
+    let out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(out, record)?;
+    Ok(())
+}
+
+/// A splitmix64 hash, for cheap reproducible per-cell randomness.
+fn hash(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// A pseudo-random value in `[0, 1)` for a grid cell.
+fn cell_random(seed: u64, x: usize, y: usize, channel: u64) -> f64 {
+    let h = hash(seed ^ hash(x as u64 ^ hash(y as u64 ^ hash(channel))));
+    (h >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// The range of grid indices sampled along each axis. With --view-sampling
+/// this is only the part covering the view and its margin, otherwise it's the
+/// whole of `[-bounds, bounds)`.
+fn grid_axes(args: &Args) -> (Range<usize>, Range<usize>) {
+    let (delta_re, delta_im) = args.grid_spacing();
+    let len = |delta: f64| {
+        (0_u32..)
+            .map(|i| -args.bounds + i as f64 * delta)
+            .take_while(|&x| x < args.bounds)
+            .count()
+    };
+    let (len_re, len_im) = (len(delta_re), len(delta_im));
+    if !args.view_sampling {
+        return (0..len_re, 0..len_im);
+    }
+
+    let width = args.size as f64 / args.zoom;
+    let reach = width * (0.5 + args.view_margin);
+    let axis = |centre: f64, delta: f64, len: usize| {
+        let index = |v: f64| ((v + args.bounds) / delta).max(0.0) as usize;
+        let start = index(centre - reach).min(len);
+        let end = (index(centre + reach) + 1).min(len);
+        start..end.max(start)
+    };
+
+    (
+        axis(-args.re_off, delta_re, len_re),
+        axis(-args.im_off, delta_im, len_im),
+    )
+}
+
+fn sample_grid(args: &Args) -> Vec<(f64, f64)> {
+    if let Some(map) = &args.seed_map {
+        return map.sample(validate::grid_points(args), args);
+    }
+    if let Some(count) = args.boundary_seed {
+        match boundary_seed::sample(count, args) {
+            Some(points) => return points,
+            None => eprintln!(
+                "Warning: the boundary seeding grid found no boundary, sampling the uniform grid"
+            ),
+        }
+    }
+
+    let (delta_re, delta_im) = args.grid_spacing();
+    let coord = |i: usize, delta: f64| -args.bounds + i as f64 * delta;
+
+    let offset = |ix: usize, iy: usize| match args.grid_offset {
+        GridOffset::None => (0.0, 0.0),
+        GridOffset::Half => (0.5 * delta_re, 0.5 * delta_im),
+        GridOffset::Random => (
+            cell_random(args.seed, ix, iy, 0) * delta_re,
+            cell_random(args.seed, ix, iy, 1) * delta_im,
+        ),
+    };
+
+    let (xs, ys) = grid_axes(args);
+    let mut cells: Vec<_> = xs
+        .cartesian_product(ys)
+        .step_by(args.sample_stride)
+        .collect();
+    if args.spatial_order {
+        cells.par_sort_unstable_by_key(|&(ix, iy)| morton(ix as u32, iy as u32));
+    }
+
+    cells
+        .into_iter()
+        .map(|(ix, iy)| {
+            let (dx, dy) = offset(ix, iy);
+            (coord(ix, delta_re) + dx, coord(iy, delta_im) + dy)
+        })
+        .collect()
+}
+
+/// Interleaves the bits of `x` and `y` into a Z-order curve index.
+fn morton(x: u32, y: u32) -> u64 {
+    fn spread(v: u32) -> u64 {
+        let mut v = v as u64;
+        v = (v | (v << 16)) & 0x0000_FFFF_0000_FFFF;
+        v = (v | (v << 8)) & 0x00FF_00FF_00FF_00FF;
+        v = (v | (v << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        (v | (v << 1)) & 0x5555_5555_5555_5555
+    }
+
+    spread(x) | (spread(y) << 1)
+}
+
+/// Draws every orbit in `coords`, skipping chunks not yet started once
+/// interrupted.
+fn render(
+    coords: &[(f64, f64)],
+    args: &Args,
+    bar: &ProgressBar,
+    progress_file: Option<&ProgressFile>,
+) -> Canvas {
+    if let Some(target) = args.target_quality {
+        return converge::render(coords, target, args, bar, progress_file);
+    }
+    if let Some(samples) = args.adaptive_samples {
+        return adaptive::render(coords, samples, args, bar, progress_file);
+    }
+
+    let iterate = if args.inverse_julia.is_empty() {
+        iterate_chunk
+    } else {
+        inverse_julia::iterate_chunk
+    };
+    let canvas = Canvas::new(args);
+    let chunks = coords
+        .par_chunks(args.work_chunk_len())
+        .filter(|_| !INTERRUPTED.load(Ordering::SeqCst));
+    let done = || {
+        progress::chunk_done(bar);
+        if let Some(file) = progress_file {
+            file.inc();
+        }
+    };
+
+    if args.chunk_balance {
+        // Rayon only makes a new canvas when it splits the work, rather than
+        // one per chunk, so the chunks can be small.
+        return chunks
+            .fold(
+                || canvas.clone(),
+                |drawn, c| {
+                    let drawn = iterate(c, drawn, args);
+                    done();
+                    drawn
+                },
+            )
+            .reduce(|| canvas.clone(), |blend, chunk| blend.merge(&chunk));
+    }
+
+    chunks
+        .map(|c| {
+            let chunk = iterate(c, canvas.clone(), args);
+            done();
+            chunk
+        })
+        .reduce(|| canvas.clone(), |blend, chunk| blend.merge(&chunk))
+}
+
+/// Turns colour sums into colours, after summing `scale` by `scale` blocks
+/// to get back to the output size. Pixels nothing was drawn in are white.
+fn colour_tint(sums: &[[f32; 4]], size: u32, scale: u32) -> Vec<[u8; 3]> {
+    let full = (size * scale) as usize;
+    (0..size as usize * size as usize)
+        .map(|n| {
+            let (x, y) = (n % size as usize, n / size as usize);
+            let mut sum = [0.0; 4];
+            for dy in 0..scale as usize {
+                for dx in 0..scale as usize {
+                    let p = sums[(y * scale as usize + dy) * full + x * scale as usize + dx];
+                    for c in 0..4 {
+                        sum[c] += p[c];
+                    }
+                }
+            }
+
+            if sum[3] == 0.0 {
+                [255; 3]
+            } else {
+                [0, 1, 2].map(|c| (255.0 * sum[c] / sum[3]).round() as u8)
+            }
+        })
+        .collect()
+}
+
+/// Turns the alpha of traces composited in sRGB-encoded values into light.
+/// The traces are white over black, which encode to themselves, so
+/// compositing each segment in encoded values reaches the same number the
+/// depth gives, only as an encoded value. Decoding it once per pixel gives
+/// its light.
+fn decode_perceptual(image: &mut Image) {
+    let max = u16::MAX as f64;
+    image
+        .pixels_mut()
+        .for_each(|p| p[1] = (max * srgb_decode(p[1] as f64 / max)).round() as u16);
+}
+
+/// Renders the traces at the output size, over an opaque black background,
+/// with adaptive normalization and density gamma applied. With an RGB
+/// colouring, the colour of each pixel is returned too.
+fn render_layer(
+    coords: &[(f64, f64)],
+    args: &Args,
+    bar: &ProgressBar,
+    progress_file: Option<&ProgressFile>,
+) -> (Image, Option<Vec<[u8; 3]>>) {
+    let render_args = args.scaled_for_render();
+    let draw_args = render_args.as_ref().unwrap_or(args);
+    let mut canvas = render(coords, draw_args, bar, progress_file);
+    if args.undersample_check {
+        undersample::report(&canvas.image(), draw_args);
+    }
+    if args.density_floor > 0.0 {
+        // Each segment at the base opacity adds the same depth.
+        let floor = args.density_floor * depth(draw_args.base_opacity() as f32) as f64;
+        canvas
+            .density
+            .pixels_mut()
+            .filter(|p| (p[0] as f64) < floor)
+            .for_each(|p| p[0] = 0);
+    }
+    let mut image = canvas.image();
+    if let BlendSpace::Perceptual = args.blend_space {
+        decode_perceptual(&mut image);
+    }
+    let tint = canvas
+        .colour
+        .map(|sums| colour_tint(&sums, args.size, args.render_scale.max(1)));
+
+    let mut background = Image::from_pixel(image.width(), image.height(), LumaA([0, u16::MAX]));
+    background
+        .pixels_mut()
+        .zip(image.pixels())
+        .for_each(|(o, i)| o.blend(i));
+    if render_args.is_some() {
+        background = image::imageops::resize(
+            &background,
+            args.size,
+            args.size,
+            image::imageops::FilterType::Lanczos3,
+        );
+    }
+
+    if let Some(tile) = args.adaptive_normalize {
+        clahe::equalize(&mut background, tile);
+    }
+    if args.density_gamma != 1.0 {
+        apply_density_gamma(&mut background, args.density_gamma);
+    }
+
+    (background, tint)
+}
+
+/// Writes each layer's density as a 16-bit greyscale PNG, named
+/// `<base>_r.png`, `<base>_g.png` and `<base>_b.png` for three layers, and
+/// `<base>_1.png` and so on otherwise.
+fn save_split_layers(base: &str, layers: &[Image]) -> image::ImageResult<()> {
+    for (i, layer) in layers.iter().enumerate() {
+        let suffix = if layers.len() == 3 {
+            ["r", "g", "b"][i].to_owned()
+        } else {
+            (i + 1).to_string()
+        };
+        let path = format!("{}_{}.png", base, suffix);
+
+        let grey: image::ImageBuffer<image::Luma<u16>, Vec<u16>> =
+            image::ImageBuffer::from_fn(layer.width(), layer.height(), |x, y| {
+                image::Luma([layer.get_pixel(x, y)[0]])
+            });
+        grey.save(&path)?;
+        eprintln!("Wrote {}", path);
+    }
+
+    Ok(())
+}
+
+fn combine_layers(layers: &[Image], blend: PowerBlend) -> Image {
+    let mut out = layers[0].clone();
+    for layer in &layers[1..] {
+        out.pixels_mut().zip(layer.pixels()).for_each(|(o, i)| {
+            o[0] = match blend {
+                PowerBlend::Sum => o[0].saturating_add(i[0]),
+                PowerBlend::Max => o[0].max(i[0]),
+            };
+        });
+    }
+
+    out
+}
+
+/// Spreads `count` colours along the fire gradient for --power-tint,
+/// skipping the dark end, which would hide the traces.
+fn power_colours(count: usize) -> Vec<[u8; 3]> {
+    let palette = Gradient::fire();
+    (0..count)
+        .map(|i| {
+            let t = if count > 1 {
+                i as f64 / (count - 1) as f64
+            } else {
+                1.0
+            };
+            let [r, g, b, _] = palette.sample(0.35 + 0.65 * t).0;
+            [r, g, b]
+        })
+        .collect()
+}
+
+/// Mixes the layer colours at each pixel, weighted by each layer's density.
+fn mix_tints(layers: &[Image], colours: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    (0..layers[0].len() / 2)
+        .map(|n| {
+            let mut sum = [0.0; 3];
+            let mut total = 0.0;
+            for (layer, colour) in layers.iter().zip(colours) {
+                let weight = layer.as_raw()[2 * n] as f64;
+                total += weight;
+                for c in 0..3 {
+                    sum[c] += weight * colour[c] as f64;
+                }
+            }
+
+            if total == 0.0 {
+                [255; 3]
+            } else {
+                sum.map(|s| (s / total).round() as u8)
+            }
+        })
+        .collect()
+}
+
+/// Each pass to render and its colour, if it has one.
+fn build_passes(
+    args: &Args,
+    layer_file: Option<&layers::LayerFile>,
+) -> Vec<(Args, Option<[u8; 3]>)> {
+    if let Some(file) = layer_file {
+        file.layers
+            .iter()
+            .map(|layer| (layer.apply(args), layer.colour))
+            .collect()
+    } else if !args.powers.is_empty() {
+        let colours = power_colours(args.powers.len());
+        args.powers
+            .iter()
+            .zip(colours)
+            .map(|(&pow, colour)| {
+                let mut pass = args.clone();
+                pass.pow = pow;
+                (pass, args.power_tint.then_some(colour))
+            })
+            .collect()
+    } else {
+        vec![(args.clone(), None)]
+    }
+}
+
+/// Drops grid points that can't be drawn or that --boundary-only excludes.
+fn filter_grid(
+    mut coords: Vec<(f64, f64)>,
+    args: &Args,
+    passes: &[(Args, Option<[u8; 3]>)],
+) -> Vec<(f64, f64)> {
+    // Classify up front so no chunk wastes the full limit on points that can
+    // never escape.
+    if can_reject_bulbs(args) && passes.iter().all(|(pass, _)| can_reject_bulbs(pass)) {
+        coords = coords
+            .into_par_iter()
+            .filter(|&coord| !in_main_bulbs(coord))
+            .collect();
+    }
+
+    if let Some(eps) = args.boundary_only {
+        let total = coords.len();
+        coords = coords
+            .into_par_iter()
+            .filter(|&coord| {
+                let (x, y) = warp_coordinate(coord, args);
+                boundary_distance(Complex64::new(x, y), args) < eps
+            })
+            .collect();
+        eprintln!(
+            "Boundary filter kept {} of {} grid points",
+            coords.len(),
+            total
+        );
+    }
+
+    coords
+}
+
+/// Renders every pass and turns the result into the final output image,
+/// with the overlay, crop and mirroring applied.
+fn render_image(
+    coords: &[(f64, f64)],
+    passes: &[(Args, Option<[u8; 3]>)],
+    blend: PowerBlend,
+    args: &Args,
+    bar: &ProgressBar,
+    progress_file: Option<&ProgressFile>,
+) -> RgbaImage {
+    let (layers, mut colour_tints): (Vec<_>, Vec<_>) = passes
+        .iter()
+        .map(|(pass, _)| render_layer(coords, pass, bar, progress_file))
+        .unzip();
+    if let Some(file) = progress_file {
+        file.finish();
+    }
+    if let Some(base) = &args.split_out {
+        save_split_layers(base, &layers).unwrap();
+    }
+
+    let mandel = args.overlay_mandel.then(|| mandelbrot_overlay(args));
+    let base = mandel.or_else(|| {
+        args.bg_gradient
+            .map(|ramp| gradient_background(&ramp, args))
+    });
+
+    if let Some(prefix) = &args.save_incremental {
+        for n in 1..=layers.len() {
+            let step = compose_image(&layers[..n], None, &passes[..n], blend, base.clone(), args);
+            let path = format!("{}_{:?}.png", prefix, passes[n - 1].0.pow);
+            output::save_image(&step, &path, args).unwrap();
+            eprintln!("Wrote {}", path);
+        }
+    }
+
+    // The RGB colourings are only allowed with a single pass.
+    let rgb_tint = if args.rgb_colouring() {
+        colour_tints.remove(0)
+    } else {
+        None
+    };
+    compose_image(&layers, rgb_tint, passes, blend, base, args)
+}
+
+/// Blends the passes' layers and tone maps them over `base`, with the crop
+/// and mirroring applied.
+fn compose_image(
+    layers: &[Image],
+    rgb_tint: Option<Vec<[u8; 3]>>,
+    passes: &[(Args, Option<[u8; 3]>)],
+    blend: PowerBlend,
+    base: Option<RgbaImage>,
+    args: &Args,
+) -> RgbaImage {
+    let tint = rgb_tint.or_else(|| {
+        passes.iter().any(|(_, c)| c.is_some()).then(|| {
+            let colours: Vec<_> = passes.iter().map(|(_, c)| c.unwrap_or([255; 3])).collect();
+            mix_tints(layers, &colours)
+        })
+    });
+    let background = combine_layers(layers, blend);
+
+    let mut canvas = match args.contour {
+        Some(levels) => contour::draw(&background, levels, base, args),
+        None => to_u8_image(&background, tint.as_deref(), base, args),
+    };
+    if let Some(crop) = args.crop {
+        canvas =
+            image::imageops::crop(&mut canvas, crop.x, crop.y, crop.width, crop.height).to_image();
+    }
+    let mirror_x = args.mirror_output || args.mirror_x;
+    let mirror_y = args.mirror_output || args.mirror_y;
+    if mirror_x || mirror_y {
+        canvas = mirror(canvas, mirror_x, mirror_y);
+    }
+
+    canvas
+}
+
+fn arg_error(msg: &str) -> ! {
+    clap::Error::with_description(msg, clap::ErrorKind::ValueValidation).exit()
+}
+
+fn main() {
+    let start_time = Instant::now();
+    let mut args = Args::from_args();
+
+    if let Some(exponent) = args.zoom_log {
+        args.zoom = 10_f64.powf(exponent);
+        eprintln!("Zoom: {}", args.zoom);
+    }
+
+    if let Some(out) = &args.composite_out {
+        let image = composite_images(&args.composite).unwrap_or_else(|e| arg_error(&e));
+        image.save(out).unwrap();
+        return;
+    }
+
+    if !args.merge_weighted.is_empty() {
+        merge::run(&args.merge_weighted, &args.image_name).unwrap_or_else(|e| arg_error(&e));
+        return;
+    }
+
+    if let Some(path) = &args.info {
+        info::print(path).unwrap_or_else(|e| arg_error(&e));
+        return;
+    }
+
+    validate::check(&mut args);
+
+    let layer_file = args
+        .layers
+        .as_ref()
+        .map(|path| layers::load(path).unwrap_or_else(|e| arg_error(&e)));
+
+    if let Some(path) = &args.palette_file {
+        args.palette = Some(palette::load(path).unwrap_or_else(|e| arg_error(&e)));
+    }
+    if let Some(path) = &args.escape_polygon {
+        let polygon = escape::Polygon::load(path).unwrap_or_else(|e| arg_error(&e));
+        args.bailout_polygon = Some(Arc::new(polygon));
+    }
+    if let Some(path) = &args.seed_from {
+        let map = seed::SeedMap::load(path).unwrap_or_else(|e| arg_error(&e));
+        args.seed_map = Some(Arc::new(map));
+    }
+
+    if args.autofocus {
+        autofocus::centre(&mut args);
+    }
+
+    if args.auto_exposure {
+        args.exposure = args.auto_exposure();
+        eprintln!("Exposure: {}", args.exposure);
+    }
+
+    let passes = build_passes(&args, layer_file.as_ref());
+    let blend = layer_file
+        .as_ref()
+        .and_then(|f| f.blend)
+        .unwrap_or(args.power_blend);
+
+    if args.interactive {
+        let grid = sample_grid(&args);
+        interactive::run(args, grid, layer_file.as_ref());
+        return;
+    }
+
+    if !args.bounds_sweep.is_empty() {
+        let sweep = montage::Vary::bounds(args.bounds_sweep.clone());
+        args.vary.insert(0, sweep);
+    }
+    if !args.vary.is_empty() {
+        let montage = montage::render(&args, layer_file.as_ref());
+        output::save_image(&montage, &args.image_name, &args).unwrap();
+        return;
+    }
+
+    let all_coords = filter_grid(sample_grid(&args), &args, &passes);
+
+    if args.count_only {
+        let count = all_coords
+            .par_iter()
+            .filter(|&&coord| would_draw(coord, &args))
+            .count();
+        println!("{}", count);
+        return;
+    }
+
+    if args.estimate {
+        estimate::print(&all_coords, &passes);
+        return;
+    }
+
+    let bar = ProgressBar::new((passes.len() * all_coords.len() / args.work_chunk_len()) as u64);
+    // The smoothed ETA is shown as the message, in place of indicatif's.
+    let template = match args.eta_window {
+        Some(window) => {
+            progress::smooth_eta(window);
+            "[{elapsed_precise}/{msg:8}] {wide_bar:.white} {pos:>7}/{len:7}"
+        }
+        None => "[{elapsed_precise}/{eta_precise}] {wide_bar:.white} {pos:>7}/{len:7} {msg}",
+    };
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(template)
+            .progress_chars("█▓▒░  "),
+    );
+
+    let progress_file = args.progress_file.as_ref().map(|path| {
+        ProgressFile::new(
+            Path::new(path),
+            passes.len() * all_coords.len().div_ceil(args.work_chunk_len()),
+        )
+    });
+
+    if let Some(path) = &args.axis_hist_out {
+        let bins = all_coords
+            .par_chunks(args.work_chunk_len())
+            .map(|c| {
+                let bins = axis_histogram_chunk(c, &args);
+                progress::chunk_done(&bar);
+                if let Some(file) = &progress_file {
+                    file.inc();
+                }
+                bins
+            })
+            .reduce(
+                || vec![0; args.size as usize],
+                |mut total, bins| {
+                    total.iter_mut().zip(bins).for_each(|(t, b)| *t += b);
+                    total
+                },
+            );
+
+        if let Some(file) = &progress_file {
+            file.finish();
+        }
+        write_axis_histogram(path, &bins, &args).unwrap();
+        return;
+    }
+
+    if let Some(path) = &args.orbit_stats {
+        orbit_stats::write(path, &all_coords, &args, &bar, progress_file.as_ref()).unwrap();
+        if let Some(file) = &progress_file {
+            file.finish();
+        }
+        return;
+    }
+
+    if args.partial_save {
+        ctrlc::set_handler(|| {
+            // A second Ctrl-C gives up on the partial image.
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+        })
+        .unwrap();
+    }
+
+    if let Some(max_time) = args.max_time {
+        let remaining = Duration::from_secs_f64(max_time).saturating_sub(start_time.elapsed());
+        thread::spawn(move || {
+            thread::sleep(remaining);
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        });
+    }
+
+    let canvas = render_image(
+        &all_coords,
+        &passes,
+        blend,
+        &args,
+        &bar,
+        progress_file.as_ref(),
+    );
+
+    let partial = INTERRUPTED.load(Ordering::SeqCst);
+    let path = if partial {
+        let path = partial_path(&args.image_name);
+        let reason = match args.max_time {
+            Some(max_time) if start_time.elapsed().as_secs_f64() >= max_time => "Out of time",
+            _ => "Interrupted",
+        };
+        eprintln!("{}, saving partial render to {}", reason, path);
+        path
+    } else {
+        args.image_name.clone()
+    };
+    output::save_image(&canvas, &path, &args).unwrap();
+
+    if args.record_params {
+        let record = RenderRecord {
+            version: env!("CARGO_PKG_VERSION"),
+            args: &args,
+            samples: all_coords.len(),
+            duration_secs: start_time.elapsed().as_secs_f64(),
+            partial,
+            command: env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        };
+        write_render_record(&path, &record).unwrap();
+    }
+
+    if let Some(dir) = &args.deepzoom_out {
+        let name = Path::new(&args.image_name)
+            .file_stem()
+            .map_or("image".into(), |s| s.to_string_lossy());
+        if let Err(e) = deepzoom::write(&canvas, dir, &name) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(reference) = &args.compare {
+        match compare::check(&canvas, reference, &args) {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!(
+                    "Error: the render differs from the reference by more than the tolerance"
+                );
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a command line, with the program and image names filled in.
+    pub fn args(extra: &[&str]) -> Args {
+        let argv = ["mandeltrace"].iter().chain(extra).chain(&["out.png"]);
+        let mut args = Args::from_iter(argv);
+        validate::check(&mut args);
+        args
+    }
+
+    fn density(coords: &[(f64, f64)], args: &Args) -> Vec<u32> {
+        render(coords, args, &ProgressBar::hidden(), None)
+            .density
+            .into_raw()
+    }
+
+    const SMALL: &[&str] = &["-s", "64", "-z", "16", "-d", "0.05", "-l", "50"];
+
+    fn small(extra: &[&str]) -> Args {
+        let all: Vec<_> = SMALL.iter().chain(extra).copied().collect();
+        args(&all)
+    }
+
+    #[test]
+    fn bulb_rejection_keeps_the_density() {
+        let args = small(&["-m", "Escaped"]);
+        assert!(can_reject_bulbs(&args));
+
+        let grid = sample_grid(&args);
+        let passes = build_passes(&args, None);
+        let filtered = filter_grid(grid.clone(), &args, &passes);
+        assert!(filtered.len() < grid.len());
+        let full = density(&grid, &args);
+        assert!(full.iter().any(|&d| d > 0));
+        assert_eq!(full, density(&filtered, &args));
+    }
+
+    #[test]
+    fn lyapunov_sign() {
+        let args = small(&[]);
+        // c = -1 has a superattracting period 2 cycle, c = -2 is chaotic.
+        assert!(lyapunov_exponent(Complex64::new(-1.0, 0.0), &args) < 0.0);
+        assert!(lyapunov_exponent(Complex64::new(-2.0, 0.0), &args) > 0.0);
+    }
+
+    /// Checks that every pixel on a coarse lattice maps back to itself,
+    /// returning how many had a coordinate.
+    fn round_trip(args: &Args) -> usize {
+        let pixels = (0..args.size).step_by(7).chain(Some(args.size - 1));
+        let pixels: Vec<_> = pixels.collect();
+        let mut mapped = 0;
+        for &x in &pixels {
+            for &y in &pixels {
+                if let Some(z) = to_complex_coord(x, y, args) {
+                    assert_eq!(to_image_coord(z, args), (x as i32, y as i32));
+                    mapped += 1;
+                }
+            }
+        }
+        mapped
+    }
+
+    #[test]
+    fn planar_round_trip() {
+        for &size in &["64", "301"] {
+            let mut args = args(&["-s", size]);
+            for &zoom in &[1.0, 135.0, 900.0, 1.0e7] {
+                for &(re, im) in &[(0.0, 0.0), (0.4, 0.0), (-1.7499, 0.0312), (0.25, -0.6)] {
+                    args.zoom = zoom;
+                    args.re_off = re;
+                    args.im_off = im;
+                    let side = (args.size as usize - 1) / 7 + 2;
+                    assert_eq!(round_trip(&args), side * side);
+                }
+            }
+        }
+    }
+
+    /// The escaped/trapped split from before --escape-limit and --trap-limit:
+    /// the iteration the orbit left the square at, if it did within -l.
+    fn single_limit_escape(coord: (f64, f64), args: &Args) -> Option<usize> {
+        let c = Complex64::new(coord.0, coord.1);
+        let mut z = c;
+        (1..=args.limit).find(|_| {
+            z = z * z + c;
+            z.im.abs() > args.bounds || z.re.abs() > args.bounds
+        })
+    }
+
+    #[test]
+    fn default_limits_keep_the_classification() {
+        for &mode in &["All", "Escaped", "Trapped"] {
+            let args = small(&["-m", mode]);
+            let grid = sample_grid(&args);
+            assert!(grid.len() > 1000);
+            for &coord in &grid {
+                match (
+                    run_orbit_as::<f64>(coord, &args, |_| ()),
+                    single_limit_escape(coord, &args),
+                ) {
+                    (Fate::Escaped(i), Some(j)) => assert_eq!(i, j),
+                    (Fate::Trapped, None) => {}
+                    _ => panic!("{:?} changed class in {} mode", coord, mode),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_background_is_clear_without_density() {
+        let args = small(&["--transparent-bg"]);
+        let image = Image::from_fn(16, 16, |x, _| LumaA([x as u16 * 4000, u16::MAX]));
+        let out = to_u8_image(&image, None, None, &args);
+        for (x, _, p) in out.enumerate_pixels() {
+            assert_eq!(p[3] == 0, x == 0, "alpha {} at x = {}", p[3], x);
+        }
+    }
+
+    #[test]
+    fn stereographic_round_trip() {
+        for &size in &["64", "301"] {
+            let mut args = args(&["-s", size, "--projection", "stereographic"]);
+            let half = args.size as f64 / 2.0;
+            for &zoom in &[1.0, 135.0, 900.0] {
+                for &(re, im) in &[(0.0, 0.0), (0.4, 0.0), (0.25, -0.6)] {
+                    args.zoom = zoom;
+                    args.re_off = re;
+                    args.im_off = im;
+
+                    let centre = args.size / 2;
+                    let z = to_complex_coord(centre, centre, &args).unwrap();
+                    assert_eq!(to_image_coord(z, &args), (centre as i32, centre as i32));
+
+                    let mut rim = 0;
+                    for x in 0..args.size {
+                        for y in 0..args.size {
+                            if let Some(z) = to_complex_coord(x, y, &args) {
+                                assert_eq!(to_image_coord(z, &args), (x as i32, y as i32));
+                                let pos = Complex64::new(x as f64 + 0.5, y as f64 + 0.5);
+                                if (pos - Complex64::new(half, half)).norm() > half - 2.0 {
+                                    rim += 1;
+                                }
+                            }
+                        }
+                    }
+                    assert!(rim > 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn short_orbits_are_left_out() {
+        let args = small(&["-m", "Escaped", "--min-orbit-length", "6"]);
+        let grid = sample_grid(&args);
+        let (long, short): (Vec<_>, Vec<_>) = grid.iter().partition(|&&coord| {
+            let mut len = 0;
+            run_orbit(coord, &args, |_| len += 1);
+            len >= 6
+        });
+        assert!(short
+            .iter()
+            .any(|&coord| would_draw(coord, &small(&["-m", "Escaped"]))));
+        assert!(short
+            .iter()
+            .all(|&coord| iterate_coordinate(coord, &args).is_none()));
+
+        // Without the short orbits, the default length draws the same thing.
+        let all = density(&grid, &args);
+        assert!(all.iter().any(|&d| d > 0));
+        assert_eq!(all, density(&long, &small(&["-m", "Escaped"])));
+    }
+
+    /// The mean depth per pixel of the same view at `size` pixels.
+    fn mean_depth(size: u32, auto_exposure: bool) -> f64 {
+        let size_arg = size.to_string();
+        let zoom = (size / 4).to_string();
+        let flags = [
+            "-m", "Escaped", "-s", &size_arg, "-z", &zoom, "-d", "0.05", "-l", "50",
+        ];
+        let mut args = args(&flags);
+        if auto_exposure {
+            args.exposure = args.auto_exposure();
+        }
+        let density = density(&sample_grid(&args), &args);
+        density.iter().map(|&d| d as f64).sum::<f64>() / density.len() as f64
+    }
+
+    #[test]
+    fn auto_exposure_keeps_the_brightness() {
+        let ratio = |auto| mean_depth(128, auto) / mean_depth(64, auto);
+        assert!((ratio(false) - 0.5).abs() < 0.1, "{}", ratio(false));
+        assert!((ratio(true) - 1.0).abs() < 0.1, "{}", ratio(true));
+    }
+
+    #[test]
+    fn multiplied_out_powers_match_powf() {
+        for &(re, im) in &[(0.3, -0.2), (-1.7, 0.05), (1.9, 1.9), (-0.01, 0.7)] {
+            let z = Complex64::new(re, im);
+            for &pow in &[2.0, 3.0, 4.0] {
+                let fast = power(z, pow);
+                let slow = z.powf(pow);
+                assert!(
+                    (fast - slow).norm() <= 1e-12 * slow.norm(),
+                    "{} ^ {}",
+                    z,
+                    pow
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn channels_are_stretched_separately() {
+        // Red dominates, and blue is never lit.
+        let mut image = RgbaImage::from_fn(8, 1, |x, _| {
+            let x = x as u8;
+            Rgba([200 + x, 10 * x, 0, 255])
+        });
+        normalize_channels(&mut image);
+
+        let max = |c: usize| image.pixels().map(|p| p[c]).max().unwrap();
+        assert_eq!((max(0), max(1), max(2)), (255, 255, 0));
+        // Each channel is scaled by its own factor, keeping the ratios.
+        let last = image.get_pixel(7, 0);
+        let half = image.get_pixel(3, 0);
+        assert_eq!(half[1], (30.0_f32 * 255.0 / 70.0).round() as u8);
+        assert_eq!(half[0], (203.0_f32 * 255.0 / 207.0).round() as u8);
+        assert_eq!(last[3], 255);
+    }
+
+    #[test]
+    fn srgb_curve() {
+        assert!((srgb_encode(0.5) - 0.7354).abs() < 1e-4);
+        for &v in &[0.0, 0.002, 0.0031308, 0.2, 0.5, 1.0] {
+            assert!((srgb_decode(srgb_encode(v)) - v).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn chunking_keeps_the_density() {
+        let reference = small(&["--chunk_len", "500"]);
+        let grid = sample_grid(&reference);
+        let expected = density(&grid, &reference);
+        assert!(expected.iter().any(|&d| d > 0));
+
+        for extra in &[
+            &["--chunk_len", "7"][..],
+            &["--chunk_len", "500", "--chunk-balance"],
+            &["--chunk_len", "7", "--chunk-balance"],
+        ] {
+            assert_eq!(density(&grid, &small(extra)), expected, "{:?}", extra);
+        }
+    }
+
+    #[test]
+    fn parallel_overlay_matches_a_serial_fill() {
+        let palette = Gradient::fire();
+        for extra in &[
+            &["--mb"][..],
+            &["--mb", "--escape-map"],
+            &["--mb", "--lyapunov"],
+        ] {
+            let args = small(extra);
+            let serial = RgbaImage::from_fn(args.size, args.size, |x, y| {
+                overlay_colour(x, y, &palette, &args)
+            });
+            assert_eq!(mandelbrot_overlay(&args), serial, "{:?}", extra);
+        }
+
+        // The plain overlay is red outside the set, and black inside it.
+        let args = small(&[]);
+        let overlay = mandelbrot_overlay(&args);
+        for (x, y, p) in overlay.enumerate_pixels() {
+            let outside = escapes(to_complex_coord(x, y, &args).unwrap(), &args);
+            assert_eq!(p[0] == 128, outside);
+        }
+    }
+
+    #[test]
+    fn density_below_the_floor_is_cleared() {
+        let sparse = [
+            "-m", "Escaped", "-s", "256", "-z", "64", "-d", "0.05", "-l", "50",
+        ];
+        let args = self::args(&sparse);
+        let floored = self::args(&[&sparse[..], &["--density-floor", "3"]].concat());
+        let grid = sample_grid(&args);
+        let bar = ProgressBar::hidden();
+        let density = density(&grid, &args);
+        let (plain, _) = render_layer(&grid, &args, &bar, None);
+        let (image, _) = render_layer(&grid, &floored, &bar, None);
+
+        let floor = 3.0 * depth(args.base_opacity() as f32) as f64;
+        let below = density.iter().filter(|&&d| d > 0 && (d as f64) < floor);
+        assert!(below.count() > 0);
+        for ((&d, p), q) in density.iter().zip(image.pixels()).zip(plain.pixels()) {
+            if (d as f64) < floor {
+                assert_eq!(p[0], 0);
+            } else {
+                assert_eq!(p, q);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_axes_count_each_spacing() {
+        let count = |extra: &[&str]| {
+            let (xs, ys) = grid_axes(&args(extra));
+            (xs.len(), ys.len())
+        };
+        assert_eq!(count(&[]), (400, 400));
+        assert_eq!(count(&["--delta-re", "0.02"]), (200, 400));
+        assert_eq!(count(&["--delta-im", "0.05"]), (400, 80));
+        assert_eq!(count(&["-d", "0.25", "--delta-re", "0.5"]), (8, 16));
+    }
+
+    #[test]
+    fn overlapping_traces_in_each_blend_space() {
+        // One segment at 0.3 alpha on the left, two overlapping on the right.
+        let alpha = 0.3;
+        let segment = Luma([(alpha * u16::MAX as f32).round() as u32]);
+        let mut canvas = Canvas {
+            density: Density::new(2, 1),
+            colour: None,
+        };
+        for &(x, count) in &[(0, 1), (1, 2)] {
+            for _ in 0..count {
+                let p = canvas.density.get_pixel_mut(x, 0);
+                *p = blend(segment, *p, 1.0);
+            }
+        }
+
+        let alpha_at = |image: &Image, x| image.get_pixel(x, 0)[1] as f64 / u16::MAX as f64;
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-3;
+        let over = |n: i32| 1.0 - (1.0 - alpha as f64).powi(n);
+
+        let linear = canvas.image();
+        assert!(close(alpha_at(&linear, 0), over(1)));
+        assert!(close(alpha_at(&linear, 1), over(2)));
+
+        let mut perceptual = canvas.image();
+        decode_perceptual(&mut perceptual);
+        assert!(close(alpha_at(&perceptual, 0), srgb_decode(over(1))));
+        assert!(close(alpha_at(&perceptual, 1), srgb_decode(over(2))));
+    }
+
+    #[test]
+    fn overflow_escapes() {
+        // Bounds this wide would make a grid far too large to validate.
+        let mut args = small(&["-p", "8"]);
+        args.bounds = 1e300;
+        let mut points = Vec::new();
+        let fate = run_orbit_as::<f64>((1e39, 1e39), &args, |z| points.push(z));
+        assert!(matches!(fate, Fate::Escaped(1)));
+        assert_eq!(points.len(), 1);
+        assert!(points.iter().all(|z| z.norm_sqr().is_finite()));
+    }
+
+    #[test]
+    fn unreachable_lines_are_not_drawn() {
+        let size = 64;
+        let inside = (10.0, 20.0);
+        for &bad in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(within_reach((inside, (bad, 5.0)), size), None);
+            assert_eq!(within_reach(((5.0, bad), inside), size), None);
+        }
+
+        // Both ends far off the same side.
+        assert_eq!(
+            within_reach(((-1e300, -1e300), (-1e300, 1e300)), size),
+            None
+        );
+
+        // A huge endpoint is cut back to the edge of the reach.
+        let reach = LINE_REACH * size as f64;
+        let ((x0, y0), (x1, y1)) = within_reach((inside, (1e300, 20.0)), size).unwrap();
+        assert_eq!((x0, y0), inside);
+        assert!((x1 - (size as f64 + reach)).abs() < 1e-9);
+        assert_eq!(y1, 20.0);
+    }
 }
@@ -0,0 +1,139 @@
+//! The --merge-weighted tool, which sums renders of the same view, each
+//! scaled by its own weight, to combine passes with different sample counts.
+
+use image::{DynamicImage, ImageBuffer, Rgba};
+
+/// Parses `path*weight`. A path without a weight has a weight of 1.
+fn parse_input(input: &str) -> Result<(&str, f32), String> {
+    let (path, weight) = match input.rsplit_once('*') {
+        Some((path, weight)) => {
+            let weight = weight
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid weight in '{}'", input))?;
+            (path.trim(), weight)
+        }
+        None => (input.trim(), 1.0),
+    };
+
+    if path.is_empty() {
+        return Err(format!("Missing path in '{}'", input));
+    }
+    if !weight.is_finite() || weight < 0.0 {
+        return Err(format!("Weight in '{}' must not be negative", input));
+    }
+    Ok((path, weight))
+}
+
+/// Adds up the colour of the inputs at 16 bits per channel, through an f32
+/// sum so large weights don't saturate partway, and saves the result to
+/// `out`.
+pub fn run(inputs: &[String], out: &str) -> Result<(), String> {
+    let mut sum: Option<(u32, u32, Vec<f32>)> = None;
+    for input in inputs {
+        let (path, weight) = parse_input(input)?;
+        let image = image::open(path)
+            .map_err(|e| format!("Failed to open '{}': {}", path, e))?
+            .to_rgba16();
+
+        let (w, h, total) = sum.get_or_insert_with(|| {
+            (
+                image.width(),
+                image.height(),
+                vec![0.0; image.as_raw().len()],
+            )
+        });
+        if image.dimensions() != (*w, *h) {
+            return Err(format!(
+                "'{}' is {}x{}, expected {}x{}",
+                path,
+                image.width(),
+                image.height(),
+                w,
+                h
+            ));
+        }
+
+        // Colour is summed, but alpha is the most opaque of the inputs, so
+        // weights below 1 don't make the merge see-through.
+        total
+            .iter_mut()
+            .zip(image.as_raw())
+            .enumerate()
+            .for_each(|(i, (t, &v))| {
+                if i % 4 == 3 {
+                    *t = t.max(v as f32);
+                } else {
+                    *t += v as f32 * weight;
+                }
+            });
+    }
+
+    let (w, h, total) = sum.ok_or("No images to merge")?;
+    let pixels = total
+        .into_iter()
+        .map(|v| v.round().min(u16::MAX as f32) as u16)
+        .collect();
+    let image: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_raw(w, h, pixels).unwrap();
+
+    // Formats without 16-bit support get an 8-bit copy.
+    let image = DynamicImage::ImageRgba16(image);
+    image
+        .save(out)
+        .or_else(|_| image.to_rgba8().save(out))
+        .map_err(|e| format!("Failed to save '{}': {}", out, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+
+    use super::*;
+
+    #[test]
+    fn parses_weights() {
+        assert_eq!(parse_input("a.png"), Ok(("a.png", 1.0)));
+        assert_eq!(parse_input(" dir/a*b.png * 0.5"), Ok(("dir/a*b.png", 0.5)));
+        assert_eq!(
+            parse_input("a.png*x"),
+            Err("Invalid weight in 'a.png*x'".to_owned())
+        );
+        assert_eq!(parse_input("*2"), Err("Missing path in '*2'".to_owned()));
+        assert!(parse_input("a.png*-1")
+            .unwrap_err()
+            .ends_with("must not be negative"));
+        assert!(parse_input("a.png*inf").is_err());
+    }
+
+    #[test]
+    fn sums_weighted_colour() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+        let (a, b, out) = (
+            path("mandeltrace-merge-a.png"),
+            path("mandeltrace-merge-b.png"),
+            path("mandeltrace-merge-out.png"),
+        );
+        RgbaImage::from_pixel(2, 1, Rgba([100, 200, 0, 80]))
+            .save(&a)
+            .unwrap();
+        RgbaImage::from_pixel(2, 1, Rgba([100, 250, 10, 255]))
+            .save(&b)
+            .unwrap();
+
+        run(&[a.clone(), format!("{}*0.5", b)], &out).unwrap();
+        let merged = image::open(&out).unwrap().to_rgba8();
+        // Colour is summed with the weights and clipped, alpha is the more
+        // opaque.
+        assert_eq!(merged.get_pixel(1, 0), &Rgba([150, 255, 5, 255]));
+
+        RgbaImage::new(3, 1).save(&b).unwrap();
+        let error = run(&[a.clone(), b.clone()], &out).unwrap_err();
+        assert!(error.ends_with("is 3x1, expected 2x1"), "{}", error);
+        assert_eq!(run(&[], &out), Err("No images to merge".to_owned()));
+
+        for path in &[a, b, out] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! The --vary parameter sweep, which renders every combination of a few
+//! varied arguments and tiles the renders into one montage.
+//!
+//! The first --vary runs along the columns, and the combinations of the rest
+//! down the rows, with the last varying fastest. There is no font to label
+//! the cells with, so the arguments of each cell are printed instead.
+//! --bounds-sweep is a --vary of the bounds put in front of the others.
+
+use std::str::FromStr;
+
+use image::RgbaImage;
+use indicatif::ProgressBar;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    build_passes, filter_grid, layers::LayerFile, render_image, sample_grid, validate, Args,
+};
+
+/// The arguments that can be varied.
+const KEYS: &[&str] = &[
+    "bounds", "delta", "limit", "zoom", "re_off", "im_off", "opacity", "pow", "seed",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Vary {
+    key: String,
+    values: Vec<f64>,
+}
+
+impl FromStr for Vary {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, values) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=v1,v2,...: '{}'", s))?;
+        let key = key.trim();
+        if !KEYS.contains(&key) {
+            return Err(format!(
+                "Can't vary '{}', expected one of: {}",
+                key,
+                KEYS.join(", ")
+            ));
+        }
+
+        let integer = matches!(key, "limit" | "opacity" | "seed");
+        let values = values
+            .split(',')
+            .map(|v| match v.trim().parse::<f64>() {
+                Ok(v) if v.is_finite() && (!integer || (v >= 0.0 && v.fract() == 0.0)) => Ok(v),
+                _ => Err(format!("Invalid value for {}: '{}'", key, v)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            key: key.to_owned(),
+            values,
+        })
+    }
+}
+
+impl Vary {
+    /// The sweep made by --bounds-sweep.
+    pub fn bounds(values: Vec<f64>) -> Self {
+        Self {
+            key: "bounds".to_owned(),
+            values,
+        }
+    }
+}
+
+fn apply(args: &mut Args, key: &str, value: f64) {
+    match key {
+        "bounds" => args.bounds = value,
+        "delta" => args.delta = value,
+        "limit" => args.limit = value as usize,
+        "zoom" => args.zoom = value,
+        "re_off" => args.re_off = value,
+        "im_off" => args.im_off = value,
+        "opacity" => args.opacity = value.min(u16::MAX as f64) as u16,
+        "pow" => args.pow = value,
+        "seed" => args.seed = value as u64,
+        _ => unreachable!("unknown key '{}'", key),
+    }
+}
+
+/// Renders every combination and tiles them, row by row.
+pub fn render(args: &Args, layer_file: Option<&LayerFile>) -> RgbaImage {
+    let columns = args.vary[0].values.len() as u32;
+    let combinations: Vec<Vec<(&str, f64)>> = args
+        .vary
+        .iter()
+        .map(|vary| vary.values.iter().map(move |&v| (vary.key.as_str(), v)))
+        .multi_cartesian_product()
+        .collect();
+    // The first --vary is the column, but the product varies it slowest.
+    let rows = combinations.len() as u32 / columns;
+
+    let mut montage: Option<RgbaImage> = None;
+    for (n, combination) in combinations.iter().enumerate() {
+        let (column, row) = (n as u32 / rows, n as u32 % rows);
+        let mut cell = args.clone();
+        for &(key, value) in combination {
+            apply(&mut cell, key, value);
+        }
+        validate::check(&mut cell);
+        if cell.auto_exposure {
+            cell.exposure = cell.auto_exposure();
+        }
+
+        let label = combination
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .join(" ");
+        eprintln!("Row {}, column {}: {}", row + 1, column + 1, label);
+
+        let passes = build_passes(&cell, layer_file);
+        let blend = layer_file.and_then(|f| f.blend).unwrap_or(cell.power_blend);
+        let coords = filter_grid(sample_grid(&cell), &cell, &passes);
+        let bar = ProgressBar::new((passes.len() * coords.len() / cell.work_chunk_len()) as u64);
+        let image = render_image(&coords, &passes, blend, &cell, &bar, None);
+        bar.finish_and_clear();
+
+        let (w, h) = image.dimensions();
+        let montage = montage.get_or_insert_with(|| RgbaImage::new(w * columns, h * rows));
+        image::imageops::replace(montage, &image, column * w, row * h);
+    }
+
+    montage.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::args;
+
+    #[test]
+    fn parses_sweeps() {
+        let vary: Vary = " zoom=100, 250.5".parse().unwrap();
+        assert_eq!(
+            (vary.key.as_str(), vary.values),
+            ("zoom", vec![100.0, 250.5])
+        );
+
+        let error = |s: &str| s.parse::<Vary>().unwrap_err();
+        assert_eq!(error("zoom"), "Expected key=v1,v2,...: 'zoom'");
+        assert!(error("size=10").starts_with("Can't vary 'size'"));
+        assert_eq!(error("limit=10,2.5"), "Invalid value for limit: '2.5'");
+        assert_eq!(error("seed=-1"), "Invalid value for seed: '-1'");
+        assert_eq!(error("pow=2,nan"), "Invalid value for pow: 'nan'");
+    }
+
+    #[test]
+    fn applies_each_key() {
+        let mut cell = args(&[]);
+        for (key, value) in KEYS.iter().zip(1..) {
+            apply(&mut cell, key, value as f64);
+        }
+        assert_eq!(
+            (cell.bounds, cell.delta, cell.limit, cell.zoom),
+            (1.0, 2.0, 3, 4.0)
+        );
+        assert_eq!((cell.re_off, cell.im_off, cell.opacity), (5.0, 6.0, 7));
+        assert_eq!((cell.pow, cell.seed), (8.0, 9));
+
+        apply(&mut cell, "opacity", 1e9);
+        assert_eq!(cell.opacity, u16::MAX);
+    }
+
+    #[test]
+    fn tiles_every_combination() {
+        let args = args(&[
+            "-s",
+            "16",
+            "-z",
+            "4",
+            "-d",
+            "0.1",
+            "-l",
+            "20",
+            "--vary",
+            "opacity=64,4000",
+            "--vary",
+            "limit=10,20,30",
+        ]);
+        let montage = render(&args, None);
+        // Opacity along the two columns, limit down the three rows.
+        assert_eq!(montage.dimensions(), (32, 48));
+
+        let cell = |column: u32, row: u32| {
+            image::imageops::crop_imm(&montage, column * 16, row * 16, 16, 16).to_image()
+        };
+        let brightness = |column, row| cell(column, row).pixels().map(|p| p[0] as u32).sum::<u32>();
+        assert!(brightness(1, 0) > brightness(0, 0));
+        assert_ne!(cell(0, 0), cell(0, 2));
+    }
+}
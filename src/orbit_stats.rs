@@ -0,0 +1,129 @@
+//! The --orbit-stats dump, a CSV with one summary row per drawn orbit, for
+//! looking at the sample population in other tools.
+//!
+//! The columns are:
+//!
+//! - `re`, `im`: the sampled coordinate, before --warp.
+//! - `escaped`: whether the orbit left the bounds, `true` or `false`.
+//! - `escape_iteration`: the iteration it left the bounds on, or empty if it
+//!   was trapped.
+//! - `orbit_length`: how many points the orbit has, before --trim-transient.
+//! - `max_radius`: the largest `|z|` along the orbit.
+//! - `final_radius`: `|z|` of the last point.
+//!
+//! Rows are only written for the orbits `iterate_coordinate` keeps, so
+//! -m and --min-orbit-length pick the population as they do for the
+//! render. Rows are in grid order.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+
+use crate::{
+    iterate_coordinate,
+    progress::{self, ProgressFile},
+    Args,
+};
+
+struct Row {
+    coord: (f64, f64),
+    escaped_at: Option<usize>,
+    length: usize,
+    max_radius: f64,
+    final_radius: f64,
+}
+
+fn summarize(coord: (f64, f64), args: &Args) -> Option<Row> {
+    let orbit = iterate_coordinate(coord, args)?;
+    Some(Row {
+        coord,
+        escaped_at: orbit.escaped_at,
+        length: orbit.points.len(),
+        max_radius: orbit.points.iter().map(|z| z.norm()).fold(0.0, f64::max),
+        final_radius: orbit.points.last().map_or(0.0, |z| z.norm()),
+    })
+}
+
+/// Summarizes the orbit of every coordinate, a chunk at a time in parallel,
+/// and writes the rows to `path`.
+pub fn write(
+    path: &str,
+    coords: &[(f64, f64)],
+    args: &Args,
+    bar: &ProgressBar,
+    progress_file: Option<&ProgressFile>,
+) -> io::Result<()> {
+    let rows: Vec<Vec<Row>> = coords
+        .par_chunks(args.work_chunk_len())
+        .map(|c| {
+            let rows = c
+                .iter()
+                .filter_map(|&coord| summarize(coord, args))
+                .collect();
+            progress::chunk_done(bar);
+            if let Some(file) = progress_file {
+                file.inc();
+            }
+            rows
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "re,im,escaped,escape_iteration,orbit_length,max_radius,final_radius"
+    )?;
+    for row in rows.iter().flatten() {
+        let escape_iteration = row.escaped_at.map_or(String::new(), |i| i.to_string());
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{}",
+            row.coord.0,
+            row.coord.1,
+            row.escaped_at.is_some(),
+            escape_iteration,
+            row.length,
+            row.max_radius,
+            row.final_radius
+        )?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::args;
+
+    fn rows(name: &str, extra: &[&str]) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("mandeltrace-{}.csv", name));
+        let path = path.to_str().unwrap();
+        let coords = [(0.0, 0.0), (1.0, 0.0), (-1.0, 0.0)];
+        let args = args(&[&["-l", "10"], extra].concat());
+        write(path, &coords, &args, &ProgressBar::hidden(), None).unwrap();
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        text.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn summarizes_each_drawn_orbit() {
+        assert_eq!(
+            rows("orbit-stats-all", &[]),
+            [
+                "re,im,escaped,escape_iteration,orbit_length,max_radius,final_radius",
+                "0,0,false,,11,0,0",
+                // 1, 2, then 5 leaves the bounds.
+                "1,0,true,2,3,5,5",
+                "-1,0,false,,11,1,1",
+            ]
+        );
+        let escaped = rows("orbit-stats-escaped", &["-m", "Escaped"]);
+        assert_eq!(escaped[1..], ["1,0,true,2,3,5,5"]);
+    }
+}
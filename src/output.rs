@@ -0,0 +1,201 @@
+//! Encoding the final image, with per-format options the generic
+//! `ImageBuffer::save` doesn't expose.
+
+use image::{codecs::jpeg::JpegEncoder, ColorType, DynamicImage, ImageFormat, RgbaImage};
+use serde::Serialize;
+use tiff::{
+    encoder::{colortype::RGBA8, compression, Rational, TiffEncoder},
+    tags::ResolutionUnit,
+};
+
+use std::{error::Error, fs::File, io::BufWriter, str::FromStr};
+
+use crate::Args;
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+}
+
+impl FromStr for TiffCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("none") {
+            Ok(Self::None)
+        } else if s.eq_ignore_ascii_case("lzw") {
+            Ok(Self::Lzw)
+        } else if s.eq_ignore_ascii_case("deflate") {
+            Ok(Self::Deflate)
+        } else {
+            Err(format!("Unknown TIFF compression: '{}'", s))
+        }
+    }
+}
+
+/// Checks the output format supports the format specific options given,
+/// returning a description of the first that doesn't.
+pub fn check_options(args: &Args) -> Result<(), String> {
+    let format = ImageFormat::from_path(&args.image_name)
+        .map_err(|_| format!("Unknown output format for '{}'", args.image_name))?;
+    if !format.can_write() || format == ImageFormat::WebP {
+        return Err(format!("Writing {:?} images isn't supported", format));
+    }
+
+    if args.tiff_compression.is_some() && format != ImageFormat::Tiff {
+        return Err("--tiff-compression needs a TIFF output".to_owned());
+    }
+
+    match args.jpeg_quality {
+        Some(_) if format != ImageFormat::Jpeg => {
+            return Err("--jpeg-quality needs a JPEG output".to_owned())
+        }
+        Some(q) if !(1..=100).contains(&q) => {
+            return Err("JPEG quality must be between 1 and 100".to_owned())
+        }
+        _ => {}
+    }
+
+    if args.dpi.is_some() && !matches!(format, ImageFormat::Png | ImageFormat::Tiff) {
+        eprintln!(
+            "Warning: {:?} output does not support DPI metadata, ignoring --dpi",
+            format
+        );
+    }
+
+    Ok(())
+}
+
+fn save_png_with_dpi(image: &RgbaImage, path: &str, dpi: u32) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width(),
+        image.height(),
+    );
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    // pHYs stores pixels per metre, followed by a unit byte of 1 for metres.
+    let ppm = (dpi as f64 / 0.0254).round() as u32;
+    let mut phys = [0; 9];
+    phys[..4].copy_from_slice(&ppm.to_be_bytes());
+    phys[4..8].copy_from_slice(&ppm.to_be_bytes());
+    phys[8] = 1;
+    writer.write_chunk(*b"pHYs", &phys)?;
+
+    writer.write_image_data(image.as_raw())?;
+    Ok(())
+}
+
+fn save_tiff(image: &RgbaImage, path: &str, args: &Args) -> Result<(), Box<dyn Error>> {
+    fn write<C: compression::Compression>(
+        image: &RgbaImage,
+        path: &str,
+        dpi: Option<u32>,
+        compression: C,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+        let mut tiff = encoder.new_image_with_compression::<RGBA8, _>(
+            image.width(),
+            image.height(),
+            compression,
+        )?;
+        if let Some(dpi) = dpi {
+            tiff.resolution(ResolutionUnit::Inch, Rational { n: dpi, d: 1 });
+        }
+        tiff.write_data(image.as_raw())?;
+        Ok(())
+    }
+
+    match args.tiff_compression.unwrap_or(TiffCompression::None) {
+        TiffCompression::None => write(image, path, args.dpi, compression::Uncompressed),
+        TiffCompression::Lzw => write(image, path, args.dpi, compression::Lzw),
+        TiffCompression::Deflate => write(image, path, args.dpi, compression::Deflate::default()),
+    }
+}
+
+fn save_jpeg(image: &RgbaImage, path: &str, quality: u8) -> Result<(), Box<dyn Error>> {
+    // JPEG has no alpha channel.
+    let rgb = DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut encoder = JpegEncoder::new_with_quality(&mut file, quality);
+    encoder.encode(rgb.as_raw(), rgb.width(), rgb.height(), ColorType::Rgb8)?;
+    Ok(())
+}
+
+pub fn save_image(image: &RgbaImage, path: &str, args: &Args) -> Result<(), Box<dyn Error>> {
+    match (ImageFormat::from_path(path)?, args.dpi, args.jpeg_quality) {
+        (ImageFormat::Png, Some(dpi), _) => save_png_with_dpi(image, path, dpi),
+        (ImageFormat::Tiff, ..) => save_tiff(image, path, args),
+        (ImageFormat::Jpeg, _, Some(quality)) => save_jpeg(image, path, quality),
+        _ => Ok(image.save(path)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use super::*;
+
+    /// Parses a command line writing to `image`, without validating it.
+    fn parse(image: &str, extra: &[&str]) -> Args {
+        Args::from_iter(["mandeltrace"].iter().chain(extra).chain(&[image]))
+    }
+
+    #[test]
+    fn options_need_their_format() {
+        assert!(check_options(&parse("out.png", &["--dpi", "300"])).is_ok());
+        assert!(check_options(&parse("out.tif", &["--tiff-compression", "lzw"])).is_ok());
+        assert!(check_options(&parse("out.jpg", &["--jpeg-quality", "90"])).is_ok());
+
+        let error = |image, extra| check_options(&parse(image, extra)).unwrap_err();
+        assert_eq!(
+            error("out.png", &["--tiff-compression", "lzw"]),
+            "--tiff-compression needs a TIFF output"
+        );
+        assert_eq!(
+            error("out.png", &["--jpeg-quality", "90"]),
+            "--jpeg-quality needs a JPEG output"
+        );
+        assert_eq!(
+            error("out.jpg", &["--jpeg-quality", "0"]),
+            "JPEG quality must be between 1 and 100"
+        );
+        assert_eq!(error("out.xyz", &[]), "Unknown output format for 'out.xyz'");
+    }
+
+    #[test]
+    fn lossless_formats_round_trip() {
+        let image = RgbaImage::from_fn(7, 5, |x, y| {
+            image::Rgba([x as u8 * 30, y as u8 * 50, 9, 200])
+        });
+        let cases: &[(&str, &[&str])] = &[
+            ("png", &["--dpi", "300"]),
+            ("tif", &[]),
+            ("tif", &["--tiff-compression", "lzw", "--dpi", "72"]),
+        ];
+        for (n, (extension, extra)) in cases.iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("mandeltrace-output-{}.{}", n, extension));
+            let path = path.to_str().unwrap();
+            save_image(&image, path, &parse(path, extra)).unwrap();
+            let read = image::open(path).unwrap().to_rgba8();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(read, image, "{} {:?}", extension, extra);
+        }
+
+        // The image crate's TIFF decoder can't read Deflate back, so this
+        // only checks it writes.
+        let path = std::env::temp_dir().join("mandeltrace-output-deflate.tif");
+        let path = path.to_str().unwrap();
+        let args = parse(path, &["--tiff-compression", "deflate"]);
+        save_image(&image, path, &args).unwrap();
+        assert!(std::fs::metadata(path).unwrap().len() > 0);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! Colour gradients for mapping a value in `[0, 1]` to a colour.
+//!
+//! Gradients for --palette-file are read from either a list of stops, one
+//! `position colour` pair per line with the colour as `#rrggbb`:
+//!
+//! ```text
+//! 0.0 #000000
+//! 0.4 #1e2a78
+//! 1.0 #ffffff
+//! ```
+//!
+//! or a GIMP gradient (`.ggr`). GIMP segments are always blended linearly in
+//! RGB, ignoring their midpoint, blending function and alpha.
+
+use image::Rgba;
+
+use std::fs;
+
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    /// Positions in `[0, 1]` and their colours, sorted by position.
+    stops: Vec<(f64, [f64; 3])>,
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<(f64, [f64; 3])>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops }
+    }
+
+    /// Black through deep blue and orange to white.
+    pub fn fire() -> Self {
+        Self::new(vec![
+            (0.0, [0.0, 0.0, 0.0]),
+            (0.3, [20.0, 30.0, 120.0]),
+            (0.7, [230.0, 120.0, 20.0]),
+            (1.0, [255.0, 255.0, 255.0]),
+        ])
+    }
+
+    /// Deep blue through cyan and yellow to red, for --heatmap.
+    pub fn heat() -> Self {
+        Self::new(vec![
+            (0.0, [30.0, 40.0, 160.0]),
+            (0.35, [0.0, 190.0, 220.0]),
+            (0.65, [240.0, 220.0, 40.0]),
+            (1.0, [220.0, 30.0, 20.0]),
+        ])
+    }
+
+    pub fn sample(&self, t: f64) -> Rgba<u8> {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let upper = self
+            .stops
+            .iter()
+            .position(|&(pos, _)| pos >= t)
+            .unwrap_or(self.stops.len() - 1);
+        let lower = upper.saturating_sub(1);
+
+        let (p0, c0) = self.stops[lower];
+        let (p1, c1) = self.stops[upper];
+        let f = if p1 > p0 { (t - p0) / (p1 - p0) } else { 0.0 };
+        let channel = |i: usize| (c0[i] + (c1[i] - c0[i]) * f).round() as u8;
+
+        Rgba([channel(0), channel(1), channel(2), 255])
+    }
+}
+
+/// Parses `#rrggbb`.
+pub fn parse_hex(s: &str) -> Option<[f64; 3]> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(f64::from);
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn check_position(pos: f64) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&pos) {
+        Ok(pos)
+    } else {
+        Err(format!("Position {} is outside 0 to 1", pos))
+    }
+}
+
+/// Parses one `position colour` line, allowing a comma between the two.
+fn parse_stop(line: &str) -> Result<(f64, [f64; 3]), String> {
+    let fields: Vec<_> = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+        .collect();
+    match fields[..] {
+        [pos, colour] => {
+            let colour = parse_hex(colour).ok_or_else(|| format!("Invalid colour '{}'", colour))?;
+            let pos = pos
+                .parse()
+                .map_err(|_| format!("Invalid position '{}'", pos))?;
+            Ok((check_position(pos)?, colour))
+        }
+        _ => Err("Expected a position and a #rrggbb colour".to_owned()),
+    }
+}
+
+/// Parses the stops at both ends of one GIMP gradient segment, whose line
+/// starts `left middle right r g b a r g b a` with colours from 0 to 1.
+fn parse_ggr_segment(line: &str) -> Result<[(f64, [f64; 3]); 2], String> {
+    let fields = line
+        .split_whitespace()
+        .take(11)
+        .map(|f| {
+            f.parse::<f64>()
+                .map_err(|_| format!("Invalid number '{}'", f))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if fields.len() < 11 {
+        return Err("Expected at least 11 numbers in a GIMP segment".to_owned());
+    }
+
+    let colour = |i: usize| {
+        [
+            255.0 * fields[i],
+            255.0 * fields[i + 1],
+            255.0 * fields[i + 2],
+        ]
+    };
+    Ok([
+        (check_position(fields[0])?, colour(3)),
+        (check_position(fields[2])?, colour(7)),
+    ])
+}
+
+pub fn load(path: &str) -> Result<Gradient, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("Couldn't read palette '{}': {}", path, e))?;
+    let line_error = |n: usize, e: String| format!("Palette '{}' line {}: {}", path, n + 1, e);
+
+    let mut lines = text.lines().enumerate();
+    let mut stops = Vec::new();
+    if text.starts_with("GIMP Gradient") {
+        // The header, an optional name, then the segment count.
+        lines.next();
+        let count = lines
+            .by_ref()
+            .find(|(_, l)| !l.starts_with("Name:"))
+            .ok_or_else(|| format!("Palette '{}' has no segment count", path))?;
+        count
+            .1
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| line_error(count.0, "Invalid segment count".to_owned()))?;
+
+        for (n, line) in lines.filter(|(_, l)| !l.trim().is_empty()) {
+            stops.extend(parse_ggr_segment(line).map_err(|e| line_error(n, e))?);
+        }
+    } else {
+        for (n, line) in lines.filter(|(_, l)| !l.trim().is_empty()) {
+            stops.push(parse_stop(line).map_err(|e| line_error(n, e))?);
+        }
+    }
+
+    if stops.len() < 2 {
+        return Err(format!("Palette '{}' needs at least two stops", path));
+    }
+
+    Ok(Gradient::new(stops))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `text` to a file named after the test, and loads it.
+    fn load_text(name: &str, text: &str) -> Result<Gradient, String> {
+        let path = std::env::temp_dir().join(format!("mandeltrace-{}", name));
+        fs::write(&path, text).unwrap();
+        let gradient = load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        gradient
+    }
+
+    #[test]
+    fn samples_between_stops() {
+        let gradient = Gradient::new(vec![(1.0, [200.0, 0.0, 100.0]), (0.0, [0.0; 3])]);
+        assert_eq!(gradient.sample(0.0), Rgba([0, 0, 0, 255]));
+        assert_eq!(gradient.sample(0.25), Rgba([50, 0, 25, 255]));
+        assert_eq!(gradient.sample(1.0), Rgba([200, 0, 100, 255]));
+        assert_eq!(gradient.sample(2.0), gradient.sample(1.0));
+        assert_eq!(gradient.sample(f64::NAN), gradient.sample(0.0));
+    }
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!(parse_hex("#1e2aFF"), Some([30.0, 42.0, 255.0]));
+        assert_eq!(parse_hex("1e2a78"), None);
+        assert_eq!(parse_hex("#1e2a7"), None);
+        assert_eq!(parse_hex("#1e2a7g"), None);
+    }
+
+    #[test]
+    fn loads_stop_lists() {
+        let gradient =
+            load_text("stops.txt", "0.0 #000000\n\n1.0, #ff0000\n0.5 #0000ff\n").unwrap();
+        assert_eq!(gradient.sample(0.5), Rgba([0, 0, 255, 255]));
+        assert_eq!(gradient.sample(0.75), Rgba([128, 0, 128, 255]));
+
+        let error = load_text("bad-stop.txt", "0.0 #000000\n1.5 #ffffff\n").unwrap_err();
+        assert!(
+            error.ends_with("line 2: Position 1.5 is outside 0 to 1"),
+            "{}",
+            error
+        );
+        assert!(load_text("one-stop.txt", "0.0 #000000\n").is_err());
+    }
+
+    #[test]
+    fn loads_gimp_gradients() {
+        let ggr = "GIMP Gradient\nName: Test\n2\n\
+                   0 0.25 0.5 0 0 0 1 1 0 0 1 0 0\n\
+                   0.5 0.75 1 1 0 0 1 1 1 1 1 0 0\n";
+        let gradient = load_text("test.ggr", ggr).unwrap();
+        assert_eq!(gradient.sample(0.25), Rgba([128, 0, 0, 255]));
+        assert_eq!(gradient.sample(1.0), Rgba([255, 255, 255, 255]));
+
+        let error = load_text("short.ggr", "GIMP Gradient\n1\n0 0.5 1 0 0 0\n").unwrap_err();
+        assert!(error.contains("at least 11 numbers"), "{}", error);
+    }
+}
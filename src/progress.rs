@@ -0,0 +1,177 @@
+//! Progress reporting to a file, for monitors that can't see the terminal,
+//! and the smoothed --eta-window estimate on the bar.
+//!
+//! The status is written to a temporary file beside the target and renamed
+//! over it, so a reader never sees a half written file.
+
+use indicatif::{FormattedDuration, ProgressBar};
+use serde::Serialize;
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// An exponential moving average of the time between finished chunks.
+/// Timing the gaps between completions, rather than each chunk, accounts
+/// for chunks running in parallel.
+struct Smoothing {
+    /// How much each new gap counts, `2 / (window + 1)`.
+    weight: f64,
+    last: Option<Instant>,
+    gap: Option<f64>,
+}
+
+/// The --eta-window estimate, if it's enabled.
+static ETA: Mutex<Option<Smoothing>> = Mutex::new(None);
+
+/// Shows a smoothed ETA, averaged over roughly the last `window` chunks, as
+/// the bar's message.
+pub fn smooth_eta(window: usize) {
+    *ETA.lock().unwrap() = Some(Smoothing {
+        weight: 2.0 / (window as f64 + 1.0),
+        last: None,
+        gap: None,
+    });
+}
+
+/// Counts a finished chunk on the bar, updating the smoothed ETA.
+pub fn chunk_done(bar: &ProgressBar) {
+    bar.inc(1);
+
+    let mut eta = ETA.lock().unwrap();
+    let smoothing = match eta.as_mut() {
+        Some(smoothing) => smoothing,
+        None => return,
+    };
+    let now = Instant::now();
+    // A new bar starts timing again, rather than counting the time spent
+    // between bars.
+    if let (Some(last), true) = (smoothing.last, bar.position() > 1) {
+        let gap = now.duration_since(last).as_secs_f64();
+        let average = match smoothing.gap {
+            Some(average) => average + smoothing.weight * (gap - average),
+            None => gap,
+        };
+        smoothing.gap = Some(average);
+
+        let remaining = bar.length().saturating_sub(bar.position());
+        let eta = Duration::from_secs_f64(average * remaining as f64);
+        bar.set_message(FormattedDuration(eta).to_string());
+    }
+    smoothing.last = Some(now);
+}
+
+#[derive(Serialize)]
+struct Status {
+    done: usize,
+    total: usize,
+    percent: f64,
+    finished: bool,
+}
+
+pub struct ProgressFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    total: usize,
+    done: AtomicUsize,
+    /// Held while writing, so threads don't race on the temporary file.
+    writing: Mutex<()>,
+}
+
+impl ProgressFile {
+    pub fn new(path: &Path, total: usize) -> Self {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+
+        Self {
+            path: path.to_owned(),
+            temp_path: temp_path.into(),
+            total,
+            done: AtomicUsize::new(0),
+            writing: Mutex::new(()),
+        }
+    }
+
+    fn write(&self, finished: bool) -> io::Result<()> {
+        let done = self.done.load(Ordering::SeqCst);
+        let status = Status {
+            done,
+            total: self.total,
+            percent: if self.total == 0 {
+                100.0
+            } else {
+                100.0 * done as f64 / self.total as f64
+            },
+            finished,
+        };
+
+        fs::write(&self.temp_path, serde_json::to_vec(&status)?)?;
+        fs::rename(&self.temp_path, &self.path)
+    }
+
+    /// Counts a finished chunk. If another thread is already writing, this
+    /// one skips the write rather than waiting, as the next chunk will
+    /// catch up.
+    pub fn inc(&self) {
+        self.done.fetch_add(1, Ordering::SeqCst);
+        if let Ok(_guard) = self.writing.try_lock() {
+            // A failed update is retried by the next chunk, and reported by
+            // `finish`.
+            let _ = self.write(false);
+        }
+    }
+
+    pub fn finish(&self) {
+        let _guard = self.writing.lock().unwrap();
+        if let Err(e) = self.write(true) {
+            eprintln!(
+                "Warning: couldn't write progress to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &Path) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_the_status() {
+        let path = std::env::temp_dir().join("mandeltrace-progress.json");
+        let progress = ProgressFile::new(&path, 4);
+        for _ in 0..3 {
+            progress.inc();
+        }
+        let status = read(&path);
+        assert_eq!(status["done"], 3);
+        assert_eq!(status["total"], 4);
+        assert_eq!(status["percent"], 75.0);
+        assert_eq!(status["finished"], false);
+
+        progress.finish();
+        assert_eq!(read(&path)["finished"], true);
+        assert!(!progress.temp_path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn empty_totals_are_complete() {
+        let path = std::env::temp_dir().join("mandeltrace-progress-empty.json");
+        let progress = ProgressFile::new(&path, 0);
+        progress.finish();
+        assert_eq!(read(&path)["percent"], 100.0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+//! Sampling maps for --seed-from, which draw sample points in proportion to
+//! the brightness of an earlier render instead of on a uniform grid.
+//!
+//! The map is laid over the view it's rendered with, so feeding a render (or
+//! a --split-out density) back in with the same -r, -i and -z spends the new
+//! samples where the old render was already bright. Repeating this refines
+//! the bright structure, at the cost of faint areas, which get fewer and
+//! fewer samples. Brightness isn't reweighted to compensate, so the result
+//! is deliberately biased towards what the map shows.
+
+use std::fmt;
+
+use crate::{cell_random, Args};
+
+pub struct SeedMap {
+    width: u32,
+    height: u32,
+    /// The running total of brightness over the pixels, in row order.
+    totals: Vec<f64>,
+}
+
+impl fmt::Debug for SeedMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SeedMap({}x{})", self.width, self.height)
+    }
+}
+
+impl SeedMap {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("Couldn't open seed map '{}': {}", path, e))?
+            .to_luma16();
+
+        let mut total = 0.0;
+        let totals: Vec<_> = image
+            .pixels()
+            .map(|p| {
+                total += p[0] as f64;
+                total
+            })
+            .collect();
+        if total == 0.0 {
+            return Err(format!("Seed map '{}' is completely black", path));
+        }
+
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            totals,
+        })
+    }
+
+    /// Draws `count` points, picking pixels in proportion to brightness and
+    /// a uniformly random position within each, mapped onto the view.
+    pub fn sample(&self, count: usize, args: &Args) -> Vec<(f64, f64)> {
+        let total = *self.totals.last().unwrap();
+        let half = args.size as f64 / 2.0;
+        let scale_x = args.size as f64 / self.width as f64;
+        let scale_y = args.size as f64 / self.height as f64;
+
+        (0..count)
+            .map(|i| {
+                let target = cell_random(args.seed, i, 0, 0) * total;
+                let n = self
+                    .totals
+                    .partition_point(|&t| t <= target)
+                    .min(self.totals.len() - 1);
+                let x = (n as u32 % self.width) as f64 + cell_random(args.seed, i, 0, 1);
+                let y = (n as u32 / self.width) as f64 + cell_random(args.seed, i, 0, 2);
+
+                (
+                    (x * scale_x - half) / args.zoom - args.re_off,
+                    (y * scale_y - half) / args.zoom - args.im_off,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+    use crate::{tests::args, to_image_pos};
+
+    /// Saves `map` under a name for the test, and loads it.
+    fn load(name: &str, map: &GrayImage) -> Result<SeedMap, String> {
+        let path = std::env::temp_dir().join(format!("mandeltrace-{}.png", name));
+        let path = path.to_str().unwrap();
+        map.save(path).unwrap();
+        let seed = SeedMap::load(path);
+        std::fs::remove_file(path).unwrap();
+        seed
+    }
+
+    #[test]
+    fn samples_only_lit_pixels() {
+        // Of four columns, only the third is lit.
+        let map = GrayImage::from_fn(4, 2, |x, _| Luma([if x == 2 { 255 } else { 0 }]));
+        let seed = load("seed-column", &map).unwrap();
+        let args = args(&["-s", "64", "-z", "16"]);
+
+        let points = seed.sample(500, &args);
+        assert_eq!(points.len(), 500);
+        for &(re, im) in &points {
+            let (x, y) = to_image_pos(num::complex::Complex64::new(re, im), &args);
+            assert!((32.0..48.0).contains(&x), "x = {}", x);
+            assert!((0.0..64.0).contains(&y), "y = {}", y);
+        }
+        // Spread within the column rather than piled on one point.
+        assert!(points.iter().any(|p| p.1 < -1.0) && points.iter().any(|p| p.1 > 1.0));
+    }
+
+    #[test]
+    fn rejects_black_maps() {
+        let error = load("seed-black", &GrayImage::new(3, 3)).unwrap_err();
+        assert!(error.ends_with("is completely black"), "{}", error);
+    }
+}
@@ -0,0 +1,103 @@
+//! The --undersample-check diagnostic, which looks at how many segments
+//! were drawn through each lit pixel to judge whether the grid is too coarse
+//! for the zoom.
+//!
+//! Traces are drawn as lines, so even a single orbit segment lights a run of
+//! pixels, and a coarse grid shows up as lit pixels with only one or two
+//! hits rather than as truly lone dots. Both are reported: pixels with at
+//! most two hits, and the specks among them with at most two lit
+//! neighbours.
+
+use crate::{Args, Image};
+
+/// Above this fraction of lit pixels having at most two hits, the render is
+/// reported as undersampled.
+const WARN_SPARSE: f64 = 0.1;
+/// Above this fraction of lit pixels being specks, likewise.
+const WARN_SPECKS: f64 = 0.01;
+
+/// Roughly how many segments were drawn through a pixel, from its alpha and
+/// the opacity of a single segment.
+fn hits(alpha: u16, opacity: u16) -> f64 {
+    let max = u16::MAX as f64;
+    if opacity as f64 >= max {
+        return if alpha > 0 { 1.0 } else { 0.0 };
+    }
+
+    (1.0 - alpha as f64 / max).ln() / (1.0 - opacity as f64 / max).ln()
+}
+
+/// Counts the sparse pixels and specks on the canvas, and prints them with
+/// a recommendation.
+pub fn report(canvas: &Image, args: &Args) {
+    let (w, h) = canvas.dimensions();
+    let alpha = |x: u32, y: u32| canvas.get_pixel(x, y)[1];
+    let opacity = args.base_opacity();
+
+    let mut lit = 0_u64;
+    let mut sparse = 0_u64;
+    let mut specks = 0_u64;
+    for y in 0..h {
+        for x in 0..w {
+            let a = alpha(x, y);
+            if a == 0 {
+                continue;
+            }
+            lit += 1;
+
+            if hits(a, opacity) >= 2.5 {
+                continue;
+            }
+            sparse += 1;
+
+            // Neighbours off the edge of the canvas count as empty.
+            let lit_neighbours = (y.saturating_sub(1)..=(y + 1).min(h - 1))
+                .flat_map(|ny| (x.saturating_sub(1)..=(x + 1).min(w - 1)).map(move |nx| (nx, ny)))
+                .filter(|&(nx, ny)| (nx, ny) != (x, y) && alpha(nx, ny) != 0)
+                .count();
+            if lit_neighbours <= 2 {
+                specks += 1;
+            }
+        }
+    }
+
+    let fraction = |n: u64| if lit == 0 { 0.0 } else { n as f64 / lit as f64 };
+    eprintln!(
+        "Undersample check: of {} lit pixels, {:.2}% have at most two hits and {:.2}% are lone specks",
+        lit,
+        100.0 * fraction(sparse),
+        100.0 * fraction(specks)
+    );
+    if fraction(sparse) > WARN_SPARSE || fraction(specks) > WARN_SPECKS {
+        eprintln!(
+            "Warning: the grid looks too coarse for this zoom. Try -d {} or lower",
+            args.delta / 2.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_invert_repeated_blending() {
+        let max = u16::MAX as f64;
+        let opacity = 640;
+        for n in 1..6 {
+            let alpha = max * (1.0 - (1.0 - opacity as f64 / max).powi(n));
+            let estimate = hits(alpha.round() as u16, opacity);
+            assert!(
+                (estimate - n as f64).abs() < 0.01,
+                "{} hits read as {}",
+                n,
+                estimate
+            );
+        }
+        assert_eq!(hits(0, opacity), 0.0);
+
+        // Opaque segments can only tell lit from unlit.
+        assert_eq!(hits(u16::MAX, u16::MAX), 1.0);
+        assert_eq!(hits(0, u16::MAX), 0.0);
+    }
+}
@@ -0,0 +1,302 @@
+//! Sanity checks for argument combinations that would otherwise render
+//! nothing, hang, or quietly produce something other than what was asked for.
+//!
+//! Problems with an obvious correction can be clamped with `--auto-fix`
+//! instead of exiting.
+
+use crate::{
+    arg_error, escape::EscapeShape, grid_axes, output, AntiAlias, Args, DrawMode, Projection, Warp,
+};
+
+/// Grids smaller than this are almost certainly a mistake with `-d` or `-b`.
+const MIN_GRID_POINTS: usize = 100;
+
+/// Either applies `fix` and warns, when `--auto-fix` was given, or exits with
+/// `msg`.
+fn fixable(args: &mut Args, msg: &str, fix: impl FnOnce(&mut Args)) {
+    if args.auto_fix {
+        eprintln!("Warning: {}, fixing", msg);
+        fix(args);
+    } else {
+        arg_error(&format!("{} (use --auto-fix to correct this)", msg));
+    }
+}
+
+/// The number of points sampled, from the grid, --seed-from or
+/// --boundary-seed.
+pub fn grid_points(args: &Args) -> usize {
+    if let Some(count) = args.seed_samples.or(args.boundary_seed) {
+        return count;
+    }
+    let (xs, ys) = grid_axes(args);
+    xs.len() * ys.len() / args.sample_stride
+}
+
+pub fn check(args: &mut Args) {
+    if args.size == 0 {
+        arg_error("Image size must be at least 1");
+    }
+
+    if args.bounds.is_nan() || args.bounds <= 0.0 {
+        fixable(args, "Bounds must be positive", |a| {
+            a.bounds = if a.bounds < 0.0 { -a.bounds } else { 2.0 };
+        });
+    }
+
+    if args.delta.is_nan() || args.delta <= 0.0 {
+        fixable(args, "Delta must be positive", |a| {
+            a.delta = if a.delta < 0.0 { -a.delta } else { 0.01 };
+        });
+    }
+
+    if args.delta >= 2.0 * args.bounds {
+        fixable(args, "Delta is larger than the sampled area", |a| {
+            a.delta = 2.0 * a.bounds / 10.0;
+        });
+    }
+
+    for (name, delta) in [("real", args.delta_re), ("imaginary", args.delta_im)] {
+        match delta {
+            Some(d) if d.is_nan() || d <= 0.0 => {
+                arg_error(&format!("Delta along the {} axis must be positive", name))
+            }
+            Some(d) if d >= 2.0 * args.bounds => arg_error(&format!(
+                "Delta along the {} axis is larger than the sampled area",
+                name
+            )),
+            _ => {}
+        }
+    }
+
+    if args.zoom.is_nan() || args.zoom <= 0.0 {
+        fixable(
+            args,
+            "Zoom must be positive, negative zoom mirrors the image",
+            |a| {
+                // Zero zoom gets a zoom that fits the sampled area in the image.
+                a.zoom = if a.zoom < 0.0 {
+                    -a.zoom
+                } else {
+                    a.size as f64 / (2.0 * a.bounds)
+                };
+            },
+        );
+    }
+
+    if !(1000.0..=40000.0).contains(&args.temperature) {
+        fixable(args, "Temperature must be between 1000K and 40000K", |a| {
+            a.temperature = a.temperature.clamp(1000.0, 40000.0);
+        });
+    }
+
+    if args.density_gamma.is_nan() || args.density_gamma <= 0.0 {
+        arg_error("Density gamma must be positive");
+    }
+    if args.display_gamma.is_nan() || args.display_gamma <= 0.0 {
+        arg_error("Display gamma must be positive");
+    }
+    if args.max_time.is_some_and(|t| !t.is_finite() || t < 0.0) {
+        arg_error("Max time must be a number of seconds, at least 0");
+    }
+    if args.tolerance.is_nan() || args.tolerance < 0.0 {
+        arg_error("Tolerance must not be negative");
+    }
+
+    if args.opacity == 0 {
+        fixable(args, "Opacity of 0 draws nothing", |a| a.opacity = 1);
+    }
+
+    if args.sample_stride == 0 {
+        fixable(args, "Sample stride must be at least 1", |a| {
+            a.sample_stride = 1
+        });
+    }
+
+    if args.render_scale == 0 {
+        fixable(args, "Render scale must be at least 1", |a| {
+            a.render_scale = 1
+        });
+    }
+    if args.size.checked_mul(args.render_scale).is_none() {
+        arg_error("Size times render scale is too large");
+    }
+
+    if args.adaptive_normalize == Some(0) {
+        fixable(
+            args,
+            "Adaptive normalization tile size must be at least 1",
+            |a| a.adaptive_normalize = None,
+        );
+    }
+
+    // Escaped mode stops at the escape limit, so it never sees the trap limit.
+    if !matches!(args.mode, DrawMode::Escaped) && args.escape_limit() > args.trap_limit() {
+        fixable(
+            args,
+            "The escape limit can't be higher than the trap limit",
+            |a| a.trap_limit = Some(a.escape_limit()),
+        );
+    }
+
+    if let Some(crop) = args.crop {
+        let fits_x = crop.x as u64 + crop.width as u64 <= args.size as u64;
+        let fits_y = crop.y as u64 + crop.height as u64 <= args.size as u64;
+        if crop.width == 0 || crop.height == 0 || !fits_x || !fits_y {
+            arg_error(&format!(
+                "Crop {}x{}+{}+{} is outside the {}x{} image",
+                crop.width, crop.height, crop.x, crop.y, args.size, args.size
+            ));
+        }
+    }
+
+    if let Some(radius) = args.point_size {
+        if !radius.is_finite() || radius < 0.0 {
+            arg_error("Point size must not be negative");
+        }
+        if !matches!(args.aa, AntiAlias::Gaussian) {
+            arg_error("--point-size only applies to --aa gaussian, which plots points");
+        }
+    }
+
+    if args.trap_attractor_cluster {
+        if let DrawMode::Escaped = args.mode {
+            arg_error(
+                "--trap-attractor-cluster only colours trapped orbits, use -m trapped or all",
+            );
+        }
+    }
+
+    if args.subpixel && matches!(args.aa, AntiAlias::Gaussian) {
+        arg_error("--subpixel draws lines, so it can't be used with --aa gaussian");
+    }
+
+    if args.lyapunov && args.pow != 2.0 {
+        arg_error("Lyapunov colouring is only valid for power 2");
+    }
+    if args.double && args.pow != 2.0 {
+        arg_error("--double is only valid for power 2");
+    }
+    if !args.inverse_julia.is_empty() && args.pow != 2.0 {
+        arg_error("--inverse-julia is only valid for power 2");
+    }
+
+    if args.density_floor.is_nan() || args.density_floor < 0.0 {
+        arg_error("Density floor must not be negative");
+    }
+
+    if args.symmetrize == 0 {
+        arg_error("--symmetrize must be at least 1");
+    }
+
+    if args.contour == Some(0) {
+        arg_error("Contour levels must be at least 1");
+    }
+
+    if args.overlay_limit == Some(0) {
+        arg_error("Overlay limit must be at least 1");
+    }
+
+    if args.mandelbrot_distance_shading && args.pow != 2.0 {
+        arg_error("--mandelbrot-distance-shading is only valid for power 2");
+    }
+    if let Some(falloff) = args.overlay_falloff {
+        if falloff.is_nan() || falloff <= 0.0 {
+            arg_error("Overlay falloff must be positive");
+        }
+    }
+
+    if let Some(eps) = args.boundary_only {
+        if eps.is_nan() || eps <= 0.0 {
+            arg_error("Boundary distance must be positive");
+        }
+        if args.pow != 2.0 {
+            arg_error("--boundary-only is only valid for power 2");
+        }
+    }
+
+    if let Some(target) = args.target_quality {
+        if target.is_nan() || target < 0.0 {
+            arg_error("Target quality must not be negative");
+        }
+    }
+
+    match (args.escape_shape, &args.escape_polygon) {
+        (Some(EscapeShape::Custom), None) => {
+            arg_error("--escape-shape custom needs an --escape-polygon")
+        }
+        (Some(EscapeShape::Custom), Some(_)) => {}
+        (_, Some(_)) => arg_error("--escape-polygon needs --escape-shape custom"),
+        _ => {}
+    }
+
+    if args.bounds_sweep.iter().any(|&b| b.is_nan() || b <= 0.0) {
+        arg_error("Bounds in --bounds-sweep must be positive");
+    }
+
+    if args.eta_window == Some(0) {
+        arg_error("ETA window must be at least 1 chunk");
+    }
+
+    if args.adaptive_samples == Some(0) {
+        arg_error("Adaptive samples must be at least 1");
+    }
+
+    if args.boundary_seed == Some(0) {
+        arg_error("Boundary seed must be at least 1 point");
+    }
+    if args.boundary_resolution == 0 {
+        arg_error("Boundary resolution must be at least 1 cell");
+    }
+
+    if args.seed_samples == Some(0) {
+        arg_error("Seed samples must be at least 1");
+    }
+    if args.seed_from.is_some() {
+        if let Projection::Stereographic = args.projection {
+            arg_error("--seed-from needs a planar projection");
+        }
+    }
+
+    if args.view_sampling {
+        if args.view_margin.is_nan() || args.view_margin < 0.0 {
+            arg_error("View margin must not be negative");
+        }
+        if let Projection::Stereographic = args.projection {
+            arg_error("--view-sampling needs a planar projection, the stereographic view covers the whole plane");
+        }
+    }
+
+    if !matches!(args.warp_params.len(), 0 | 4 | 8) {
+        arg_error("Warp params must be four real values or eight re,im values");
+    }
+    if let Warp::Mobius = args.warp {
+        let [a, b, c, d] = args.mobius_coefficients();
+        if (a * d - b * c).norm_sqr() == 0.0 {
+            arg_error("Mobius warp coefficients must satisfy ad - bc != 0");
+        }
+    }
+
+    if let Err(e) = output::check_options(args) {
+        arg_error(&e);
+    }
+
+    let points = grid_points(args);
+    if points < MIN_GRID_POINTS {
+        eprintln!(
+            "Warning: the sampling grid only has {} points, try a smaller -d",
+            points
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::args;
+
+    #[test]
+    fn escaped_mode_ignores_the_trap_limit() {
+        let args = args(&["-m", "Escaped", "-l", "50", "--escape-limit", "200"]);
+        assert_eq!(args.escape_limit(), 200);
+        assert_eq!(args.trap_limit, None);
+    }
+}